@@ -36,9 +36,11 @@ fn main() -> Result<()> {
                 max_len: 100,
                 max_gap: 20,
                 mismatches: 0,
+                ..Default::default()
             },
             output_path: RUST_OUTPUT_PATH.into(),
             output_format,
+            ..Default::default()
         },
         DataChoice::Rand => Config {
             input_file: "tests/test_data/rand1000000.fasta",
@@ -48,9 +50,11 @@ fn main() -> Result<()> {
                 max_len: 100,
                 max_gap: 5,
                 mismatches: 1,
+                ..Default::default()
             },
             output_path: RUST_OUTPUT_PATH.into(),
             output_format,
+            ..Default::default()
         },
     };
 
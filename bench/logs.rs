@@ -58,7 +58,7 @@ impl TestSuite {
     }
 
     // Return a cartesian product of Configs.
-    fn to_configs_iter(&self) -> impl Iterator<Item = Config> + '_ {
+    fn to_configs_iter(&self) -> impl Iterator<Item = Config<'_>> + '_ {
         let TestSuite {
             min_len,
             max_gap,
@@ -204,7 +204,7 @@ fn main() -> Result<()> {
 
         for config in test_suite.to_configs_iter() {
             // The config doesn't make sense: skip
-            if let Err(_) = config.params.check_bounds(*size_seq) {
+            if config.params.check_bounds(*size_seq).is_err() {
                 // println!("{}", &err);
                 continue;
             }
@@ -5,6 +5,13 @@ class SearchParams:
         max_len: int,
         max_gap: int,
         mismatches: int,
+        circular: bool = False,
+        match_mode: str = "permissive",
+        rna: bool = False,
+        n_policy: str = "match-all",
+        max_edits: int | None = None,
+        kind: str = "inverted",
+        report: str = "all",
     ) -> None: ...
 
 def find_irs(
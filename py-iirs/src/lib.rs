@@ -11,13 +11,33 @@ pub struct SearchParams {
 #[pymethods]
 impl SearchParams {
     #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (min_len, max_len, max_gap, mismatches, circular = false, match_mode = "permissive", rna = false, n_policy = "match-all", max_edits = None, kind = "inverted", report = "all"))]
     pub fn new(
         min_len: usize,
         max_len: usize,
         max_gap: usize,
         mismatches: usize,
+        circular: bool,
+        match_mode: &str,
+        rna: bool,
+        n_policy: &str,
+        max_edits: Option<usize>,
+        kind: &str,
+        report: &str,
     ) -> PyResult<Self> {
-        match _iirs::SearchParams::new(min_len, max_len, max_gap, mismatches) {
+        match _iirs::SearchParams::new(min_len, max_len, max_gap, mismatches).and_then(
+            |mut inner| {
+                inner.circular = circular;
+                inner.match_mode = match_mode.parse()?;
+                inner.rna = rna;
+                inner.n_policy = n_policy.parse()?;
+                inner.max_edits = max_edits;
+                inner.kind = kind.parse()?;
+                inner.report = report.parse()?;
+                Ok(inner)
+            },
+        ) {
             Ok(inner) => Ok(Self { inner }),
             Err(e) => Err(PyErr::new::<PyValueError, _>(format!(
                 "Invalid search parameters: {:?}",
@@ -30,7 +50,7 @@ impl SearchParams {
 #[pyfunction]
 pub fn find_irs(params: &SearchParams, seq: &str) -> PyResult<Vec<(usize, usize, usize)>> {
     match _iirs::find_irs(&params.inner, seq.as_bytes()) {
-        Ok(result) => Ok(result),
+        Ok(result) => Ok(result.iter().map(_iirs::InvertedRepeat::as_tuple).collect()),
         Err(e) => Err(PyErr::new::<PyValueError, _>(format!("Error: {:?}", e))),
     }
 }
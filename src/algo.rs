@@ -1,13 +1,41 @@
 #![allow(clippy::many_single_char_names)]
 
 use rmq::Rmq;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+use crate::align::add_edit_irs;
+use crate::direct::add_direct_repeats_in_centers;
+use crate::ir::{InvertedRepeat, RepeatKind};
 use crate::{config::SearchParams, matrix::MatchMatrix};
 
+/// Element of a suffix array.
+///
+/// Suffix arrays of texts shorter than 2^31 are built with `i32` (divsufsort), longer
+/// texts need `i64`.
+pub trait SaIndex: Copy {
+    fn as_usize(self) -> usize;
+}
+
+impl SaIndex for i32 {
+    #[inline(always)]
+    fn as_usize(self) -> usize {
+        self as usize
+    }
+}
+
+impl SaIndex for i64 {
+    #[inline(always)]
+    fn as_usize(self) -> usize {
+        self as usize
+    }
+}
+
 /// Build the LCP (Longest Common Prefix) array from a suffix array.
 ///
 /// Employs a slightly modified version of the classic Kasai's algorithm.
-pub fn lcp_array(s: &[u8], s_n: usize, sa: &[i32], inv_sa: &[usize]) -> Vec<usize> {
+pub fn lcp_array<T: SaIndex>(s: &[u8], s_n: usize, sa: &[T], inv_sa: &[usize]) -> Vec<usize> {
     let mut lcp: Vec<usize> = vec![0; s_n];
     let mut j: usize;
 
@@ -16,7 +44,7 @@ pub fn lcp_array(s: &[u8], s_n: usize, sa: &[i32], inv_sa: &[usize]) -> Vec<usiz
             let l = lcp[inv_sa[i - 1]];
             j = l.saturating_sub(1);
 
-            while s[i + j] == s[sa[inv_sa[i] - 1] as usize + j] {
+            while s[i + j] == s[sa[inv_sa[i] - 1].as_usize() + j] {
                 j += 1;
             }
 
@@ -33,7 +61,9 @@ pub fn lcp_array(s: &[u8], s_n: usize, sa: &[i32], inv_sa: &[usize]) -> Vec<usiz
 /// `(i, j)` simultaneously, up to `mismatches` real mismatches (Kangaroo method).
 ///
 /// A sentinel `0` is prepended. Mismatches within the first `initial_gap` characters
-/// are recorded but do not consume the budget.
+/// are recorded but do not consume the budget. A mismatch that breaks the arms ends the
+/// extension, or, within the first `initial_gap` characters, replaces the previous
+/// locations (and the sentinel) so that the arms start after it.
 ///
 /// Note that because IUPAC matching is not transitive (A matches N, N matches G but A
 /// doesn't match G), and our helper structures (lcp, rmq) were built on exact equality,
@@ -53,10 +83,11 @@ fn real_lce_mismatches<R: Rmq>(
     j: usize,
     inv_sa: &[usize],
     rmq: &R,
-    mut mismatches: i32,
+    mut mismatches: isize,
     initial_gap: usize,
     matrix: &MatchMatrix,
-) -> Vec<u32> {
+    reached: &Reached,
+) -> Vec<usize> {
     let s_n = s.len();
     let mut mismatch_locs = vec![0];
     let mut real_lce = 0;
@@ -67,7 +98,10 @@ fn real_lce_mismatches<R: Rmq>(
         let jj = inv_sa[j + real_lce];
 
         if ii < jj {
-            real_lce += rmq.rmq(ii + 1, jj + 1).unwrap_or(0);
+            let lce = rmq.rmq(ii + 1, jj + 1).unwrap_or(0);
+            // The characters are equal, but they do not necessarily match (ex. n in a
+            // strict matrix).
+            real_lce += matrix.matching_prefix(&s[i + real_lce..i + real_lce + lce]);
         }
 
         let ni = i + real_lce;
@@ -79,8 +113,20 @@ fn real_lce_mismatches<R: Rmq>(
         }
 
         if !matrix.match_u8(s[ni], s[nj]) {
-            mismatch_locs.push((real_lce + 1) as u32);
-            if real_lce + 1 >= initial_gap {
+            reached.record(s[ni], s[nj]);
+            let breaks = matrix.breaks_u8(s[ni], s[nj]);
+            if real_lce + 1 < initial_gap {
+                // It can still be in the gap, but the arms can not cross it: they start
+                // after it.
+                if breaks {
+                    mismatch_locs.clear();
+                }
+                mismatch_locs.push(real_lce + 1);
+            } else {
+                mismatch_locs.push(real_lce + 1);
+                if breaks {
+                    break;
+                }
                 mismatches -= 1;
             }
         }
@@ -94,47 +140,113 @@ fn real_lce_mismatches<R: Rmq>(
 #[cfg(feature = "parallel")]
 use rayon::prelude::*;
 
-/// Find all IRs in `seq` and return them as `(left, right, gap)` triples.
+/// Records whether the extension of some center reached the start ('#') or the end ('$')
+/// of `seq`. If not, the result does not depend on the sequence beyond the extensions.
+#[derive(Debug, Default)]
+pub struct Reached {
+    pub start: AtomicBool,
+    pub end: AtomicBool,
+}
+
+impl Reached {
+    #[inline]
+    pub(crate) fn record(&self, si: u8, sj: u8) {
+        if si == b'$' {
+            self.end.store(true, Ordering::Relaxed);
+        }
+        if sj == b'#' {
+            self.start.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Find all IRs in `seq` whose (doubled) center `left + right` is in `centers`.
 ///
-/// Recall that `s` is `seq` concatenated with its reverse complementary.
+/// Recall that `s` is `seq` concatenated with its reverse complementary (or its reverse,
+/// for the mirror repeats). The direct repeats are searched along diagonals instead (see
+/// `add_direct_repeats_in_centers`). Of the IRs of every center, only those selected by
+/// the report mode of `params` are returned, and, with indels, those not found again at a
+/// later center (see `drop_found_later`).
 //
 // Notes:
 // - The original algorithm returned a set of tuples: BTreeSet<(i32, i32, i32)> but did no sorting.
 //   It was marginally slower (compared to Vec<(i32, i32, i32)>, while making the code less clear.
 //   >> AT NO POINT IS A DUPLICATE pushed into "irs".
-// - If we use instead a Vec<(i32, i32, 32)> the collection needs to be returned sorted if the data
+// - If we use instead a Vec<InvertedRepeat> the collection needs to be returned sorted if the data
 //   will be printed sorted afterwards in "format".
-pub fn add_irs<R: Rmq + std::marker::Sync>(
+pub fn add_irs_in_centers<R: Rmq + std::marker::Sync>(
     s: &[u8],
     inv_sa: &[usize],
     rmq: &R,
     params: &SearchParams,
     matrix: &MatchMatrix,
-) -> Vec<(usize, usize, usize)> {
+    centers: Range<usize>,
+    reached: &Reached,
+) -> Vec<InvertedRepeat> {
+    if params.kind == RepeatKind::Direct {
+        return add_direct_repeats_in_centers(s, inv_sa, rmq, params, matrix, centers, reached);
+    }
+
     let s_n = s.len();
     let n = s_n / 2 - 1;
+    let valid = params.min_len..(s_n - 1).saturating_sub(params.min_len);
+    let centers = centers.start.max(valid.start)..centers.end.min(valid.end);
+
+    let at_center =
+        |c: usize| add_irs_at_this_center(s, n, inv_sa, rmq, params, matrix, reached, c);
+    let found_at = |c: usize| {
+        let irs = match params.max_edits {
+            Some(max_edits) => drop_found_later(at_center(c), c, max_edits, valid.end, at_center),
+            None => at_center(c),
+        };
+        params.report.select_at_center(irs)
+    };
 
     // Conditional compilation for parallel execution
     #[cfg(feature = "parallel")]
-    let result: Vec<_> = (params.min_len..(s_n - 1 - params.min_len))
-        .into_par_iter()
-        .flat_map(|c| add_irs_at_this_center(s, n, inv_sa, rmq, params, matrix, c))
-        .collect();
+    let result: Vec<_> = centers.into_par_iter().flat_map(found_at).collect();
 
     // Conditional compilation for sequential execution
     #[cfg(not(feature = "parallel"))]
-    let result: Vec<_> = (params.min_len..(s_n - 1 - params.min_len))
-        .flat_map(|c| add_irs_at_this_center(s, n, inv_sa, rmq, params, matrix, c))
-        .collect();
+    let result: Vec<_> = centers.flat_map(found_at).collect();
 
     result
 }
 
+/// Drop the IRs of `irs`, found at center `c`, that are also found at a later center.
+///
+/// With indels, the same span can be aligned around different centers: an unpaired
+/// nucleotide next to the inner pair can be in either arm, with the gap moved by one.
+/// The center of the alignment differs from `left + right` by at most `max_edits`, so
+/// only a few centers are searched again, and the span is kept at the last one, with the
+/// longest left arm.
+fn drop_found_later(
+    mut irs: Vec<InvertedRepeat>,
+    c: usize,
+    max_edits: usize,
+    centers_end: usize,
+    at_center: impl Fn(usize) -> Vec<InvertedRepeat>,
+) -> Vec<InvertedRepeat> {
+    let mut later: BTreeMap<usize, Vec<InvertedRepeat>> = BTreeMap::new();
+    irs.retain(|ir| {
+        let last = (ir.left + ir.right + max_edits + 1).min(centers_end);
+        !(c + 1..last).any(|other| {
+            later
+                .entry(other)
+                .or_insert_with(|| at_center(other))
+                .iter()
+                .any(|found| found.as_tuple() == ir.as_tuple())
+        })
+    });
+    irs
+}
+
 /// Find all IRs centred at `c`.
 ///
 /// Derives arm positions, collects mismatch locations via the Kangaroo method, then uses
 /// a two-pointer sweep to emit every valid window within the mismatch budget and
 /// `[min_len, max_len]` arm length, truncating overlong arms as needed.
+#[allow(clippy::too_many_arguments)]
 fn add_irs_at_this_center<R: Rmq>(
     s: &[u8],
     n: usize,
@@ -142,8 +254,9 @@ fn add_irs_at_this_center<R: Rmq>(
     rmq: &R,
     params: &SearchParams,
     matrix: &MatchMatrix,
+    reached: &Reached,
     c: usize,
-) -> Vec<(usize, usize, usize)> {
+) -> Vec<InvertedRepeat> {
     let mut irs_at_this_center = Vec::new();
 
     // This could be computed outside of the loop.
@@ -166,15 +279,20 @@ fn add_irs_at_this_center<R: Rmq>(
     let i = (1.0 + c - margin) as usize;
     let j = (behind - c - margin) as usize;
 
+    if let Some(max_edits) = params.max_edits {
+        return add_edit_irs(s, i, j, initial_gap, params, max_edits, matrix, reached);
+    }
+
     let mismatch_locs = real_lce_mismatches(
         s,
         i,
         j,
         inv_sa,
         rmq,
-        params.mismatches as i32,
+        params.mismatches as isize,
         initial_gap,
         matrix,
+        reached,
     );
 
     // Get a list of valid start and end mismatch locations
@@ -210,7 +328,7 @@ fn add_irs_at_this_center<R: Rmq>(
             mismatch_diff = end.1 - start.1 - 1;
         }
 
-        let start_mismatch = start.0 as usize;
+        let start_mismatch = start.0;
         if start_mismatch >= initial_gap {
             break;
         }
@@ -228,7 +346,7 @@ fn add_irs_at_this_center<R: Rmq>(
         debug_assert!(end_it_ptr > start_it_ptr);
         // And since start_it_ptr >= 0 because usize, we have: end_it_ptr > 0
 
-        let end_mismatch = (valid_end_locs[end_it_ptr - 1].0 - 1) as usize;
+        let end_mismatch = valid_end_locs[end_it_ptr - 1].0 - 1;
 
         let ir_length = end_mismatch - start_mismatch;
         if ir_length < params.min_len {
@@ -241,16 +359,16 @@ fn add_irs_at_this_center<R: Rmq>(
         let gap = 2 * start_mismatch + 1 - (2.0 * margin) as usize;
         debug_assert!(gap <= params.max_gap);
 
-        let ir = if ir_length <= params.max_len {
+        let (left, right) = if ir_length <= params.max_len {
             // IR is not too long, so add to output
-            (left, right, gap)
+            (left, right)
         } else {
             // IR is too long, so attempt truncation
             let overshoot = ir_length - params.max_len;
 
             // 0 if end_it_ptr <= 2
             let prev_ptr = end_it_ptr.saturating_sub(2);
-            let prev = (valid_end_locs[prev_ptr].0 - 1) as usize;
+            let prev = valid_end_locs[prev_ptr].0 - 1;
             let mismatch_gap = if end_mismatch == prev {
                 0
             } else {
@@ -260,13 +378,32 @@ fn add_irs_at_this_center<R: Rmq>(
             // Check if truncation results in the potential IR ending in a mismatch
             if overshoot == mismatch_gap {
                 // Potential IR does end in a mismatch, so truncate a character
-                (left + overshoot + 1, right - overshoot - 1, gap)
+                (left + overshoot + 1, right - overshoot - 1)
             } else {
                 // Potential IR does not end in a mismatch, so add to output
-                (left + overshoot, right - overshoot, gap)
+                (left + overshoot, right - overshoot)
             }
         };
 
+        // The mismatches inside the arms are the ones strictly between start and end.
+        // A mismatch location `loc` corresponds to the offset `loc - 1` from the center,
+        // that is, to the position `c + margin - loc` of the left arm.
+        let end_id = valid_end_locs[end_it_ptr - 1].1;
+        let outer = (c + margin) as usize;
+        let mismatch_positions = mismatch_locs[start.1 + 1..end_id]
+            .iter()
+            .rev()
+            .map(|&loc| outer - loc)
+            .filter(|&pos| pos >= left)
+            .collect();
+
+        let ir = InvertedRepeat {
+            left,
+            right,
+            gap,
+            mismatch_positions,
+            alignment: None,
+        };
         irs_at_this_center.push(ir);
 
         start_it_ptr += 1;
@@ -0,0 +1,181 @@
+use crate::algo::Reached;
+use crate::config::SearchParams;
+use crate::ir::{ArmAlignment, InvertedRepeat};
+use crate::matrix::MatchMatrix;
+
+/// Find the IRs of a center allowing indels between the arms (see
+/// [`SearchParams::max_edits`]).
+///
+/// As in `real_lce_mismatches`, the right arm is read from `s[i..]` and the complemented
+/// left arm from `s[j..]`. Every pair of the first `initial_gap` ones can be the gap, and
+/// the arms can start after it, at a pair that matches right after one that does not (or
+/// at the first pair). From every such start, the arms are extended with a banded
+/// alignment, and the longest one within the edit budget gives the IR.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn add_edit_irs(
+    s: &[u8],
+    i: usize,
+    j: usize,
+    initial_gap: usize,
+    params: &SearchParams,
+    max_edits: usize,
+    matrix: &MatchMatrix,
+    reached: &Reached,
+) -> Vec<InvertedRepeat> {
+    let s_n = s.len();
+    let n = s_n / 2 - 1;
+    // The pairs before the end of the sequence ('$') and of its reverse complement ('#')
+    let available = n.saturating_sub(i).min(s_n - 1 - j);
+
+    let mut irs = Vec::new();
+    for start in 0..initial_gap.min(available) {
+        let (ri, lj) = (i + start, j + start);
+        let starts_arms =
+            matrix.match_u8(s[ri], s[lj]) && (start == 0 || !matrix.match_u8(s[ri - 1], s[lj - 1]));
+        if !starts_arms {
+            continue;
+        }
+        let Some(alignment) = extend_arms(s, ri, lj, params, max_edits, matrix, reached) else {
+            continue;
+        };
+
+        // The left arm ends at the position paired with `ri`, at the other side of the gap
+        let inner_left = 2 * n - lj;
+        let right_len = alignment.ops.iter().filter(|&&op| op != b'I').count();
+        let left = inner_left + 1 - alignment.left_len;
+        let right = ri + right_len - 1;
+        let mismatch_positions = alignment
+            .columns(left, right)
+            .zip(&alignment.ops)
+            .filter(|&(_, &op)| op == b'X')
+            .filter_map(|((pos, _), _)| pos)
+            .collect();
+        irs.push(InvertedRepeat {
+            left,
+            right,
+            gap: ri - inner_left - 1,
+            mismatch_positions,
+            alignment: Some(alignment),
+        });
+    }
+
+    irs
+}
+
+/// Align the arms outward from their inner pair `(s[ri], s[lj])`, which matches.
+///
+/// Edit distance in a band of `max_edits` diagonals: `cost[q][p]` is the least number of
+/// edits to pair the first `q` nucleotides of the left arm with the first `p` of the right
+/// arm. The chosen alignment is the longest (in total nucleotides) with at most `max_edits`
+/// edits, arms in `min_len..=max_len` and a matching outer pair. Ties are broken by fewer
+/// edits, then by more similar arm lengths.
+fn extend_arms(
+    s: &[u8],
+    ri: usize,
+    lj: usize,
+    params: &SearchParams,
+    max_edits: usize,
+    matrix: &MatchMatrix,
+    reached: &Reached,
+) -> Option<ArmAlignment> {
+    let s_n = s.len();
+    let e = max_edits;
+    let width = 2 * e + 1;
+    let lefts = params.max_len.min(s_n - 1 - lj);
+    let rights = params.max_len.min(s_n / 2 - 1 - ri);
+    if lefts == 0 || rights == 0 {
+        return None;
+    }
+
+    // Row `q` holds the diagonals `p - q` in `-e..=e`
+    let cell = |q: usize, p: usize| q * width + p + e - q;
+    let mut cost = vec![usize::MAX; (lefts + 1) * width];
+    let mut ops = vec![0; (lefts + 1) * width];
+    cost[cell(1, 1)] = 0;
+    ops[cell(1, 1)] = b'=';
+
+    let mut best: Option<(usize, usize)> = None;
+    let mut best_key = (0, 0, 0);
+    let (mut max_q, mut max_p) = (1, 1);
+    for q in 1..=lefts {
+        let mut alive = false;
+        let left_char = s[lj + q - 1];
+        for p in q.saturating_sub(e).max(1)..=(q + e).min(rights) {
+            if (q, p) != (1, 1) {
+                let right_char = s[ri + p - 1];
+                let mut candidates = [(usize::MAX, 0); 3];
+                // Both nucleotides are paired
+                if q > 1 && p > 1 && cost[cell(q - 1, p - 1)] <= e {
+                    let pairs = matrix.match_u8(right_char, left_char);
+                    if pairs {
+                        candidates[0] = (cost[cell(q - 1, p - 1)], b'=');
+                    } else if !matrix.breaks_u8(right_char, left_char) {
+                        candidates[0] = (cost[cell(q - 1, p - 1)] + 1, b'X');
+                    }
+                }
+                // The nucleotide of the left arm is unpaired
+                if q > 1
+                    && p < q + e
+                    && cost[cell(q - 1, p)] <= e
+                    && !matrix.breaks_u8(left_char, left_char)
+                {
+                    candidates[1] = (cost[cell(q - 1, p)] + 1, b'I');
+                }
+                // The nucleotide of the right arm is unpaired
+                if p > 1
+                    && p + e > q
+                    && cost[cell(q, p - 1)] <= e
+                    && !matrix.breaks_u8(right_char, right_char)
+                {
+                    candidates[2] = (cost[cell(q, p - 1)] + 1, b'D');
+                }
+                // The first minimum: pairs are preferred over indels
+                let (c, op) = candidates
+                    .into_iter()
+                    .fold((usize::MAX, 0), |min, c| if c.0 < min.0 { c } else { min });
+                cost[cell(q, p)] = c;
+                ops[cell(q, p)] = op;
+            }
+
+            let c = cost[cell(q, p)];
+            if c > e {
+                continue;
+            }
+            alive = true;
+            max_q = max_q.max(q);
+            max_p = max_p.max(p);
+            if ops[cell(q, p)] == b'=' && q >= params.min_len && p >= params.min_len {
+                let key = (q + p, e - c, e - q.abs_diff(p));
+                if best.is_none() || key > best_key {
+                    best = Some((q, p));
+                    best_key = key;
+                }
+            }
+        }
+        if !alive {
+            break;
+        }
+    }
+    // The extension reached the start or the end of the sequence
+    reached.record(s[ri + max_p], s[lj + max_q]);
+
+    let (left_len, mut p) = best?;
+    let mut q = left_len;
+    let mut alignment = Vec::with_capacity(q + p);
+    loop {
+        let op = ops[cell(q, p)];
+        alignment.push(op);
+        if (q, p) == (1, 1) {
+            break;
+        }
+        match op {
+            b'I' => q -= 1,
+            b'D' => p -= 1,
+            _ => (q, p) = (q - 1, p - 1),
+        }
+    }
+    Some(ArmAlignment {
+        left_len,
+        ops: alignment,
+    })
+}
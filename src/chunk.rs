@@ -0,0 +1,520 @@
+use anyhow::{Result, bail};
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+
+use crate::algo::Reached;
+use crate::config::SearchParams;
+use crate::index::{IrIndex, OrderedIrs};
+use crate::ir::InvertedRepeat;
+use crate::utils;
+
+/// Find all the [Inverted Repeats](https://en.wikipedia.org/wiki/Inverted_repeat) (IRs) in a sequence
+/// by scanning it in overlapping windows, so that only the index of a window is in memory at a time.
+///
+/// Every window reports the IRs centered in its core of `window_size` positions, and it is
+/// extended with enough context on both sides for its results to be the same as the ones of
+/// [`crate::find_irs`] over the whole sequence. That context is usually
+/// `max_len + max_gap / 2` positions, but it is grown for the windows where an IR extends
+/// further (for example, inside long runs of `n`).
+///
+/// Returns an error if the sequence contains invalid characters or `window_size` is zero.
+///
+/// # Examples
+///
+/// ```rust
+/// use iirs::{SearchParams, find_irs, find_irs_chunked};
+///
+/// let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(10);
+/// let params = SearchParams::new(10, 100, 5, 1).unwrap();
+/// let irs = find_irs_chunked(&params, seq.as_bytes(), 50).unwrap();
+/// assert_eq!(irs, find_irs(&params, seq.as_bytes()).unwrap());
+/// ```
+pub fn find_irs_chunked(
+    params: &SearchParams,
+    seq: &[u8],
+    window_size: usize,
+) -> Result<Vec<InvertedRepeat>> {
+    let mut irs = Vec::new();
+    find_irs_chunked_with(params, seq, window_size, |ir| {
+        irs.push(ir);
+        Ok(())
+    })?;
+    Ok(irs)
+}
+
+/// Same as [`find_irs_chunked`], but calling `f` on each IR (in the same order) instead
+/// of collecting them, so that the memory does not depend on the number of IRs either.
+///
+/// Stops at the first error returned by `f`.
+pub fn find_irs_chunked_with<F>(
+    params: &SearchParams,
+    seq: &[u8],
+    window_size: usize,
+    f: F,
+) -> Result<()>
+where
+    F: FnMut(InvertedRepeat) -> Result<()>,
+{
+    if window_size == 0 {
+        bail!("window_size must be greater than zero.")
+    }
+    params.check_supported()?;
+
+    let seq = utils::sanitize_sequence(seq)?;
+    let n = seq.len();
+    let cores = (0..n)
+        .step_by(window_size)
+        .map(|start| start..start.saturating_add(window_size).min(n));
+    find_irs_in_cores(params, &seq, cores, f)
+}
+
+/// Find the [Inverted Repeats](https://en.wikipedia.org/wiki/Inverted_repeat) (IRs) of a
+/// sequence centered in the given regions.
+///
+/// An IR is centered in the region `start..end` if `2 * start <= left + right < 2 * end`
+/// (with `left + right` taken modulo twice the length for a circular sequence).
+/// Its arms may extend outside of the region: they are not truncated, since every region
+/// is searched with enough context around it (as in [`find_irs_chunked`]). The result is
+/// the same as keeping the IRs of [`crate::find_irs`] centered in the regions, with the
+/// positions in the whole sequence.
+///
+/// Returns an error if the sequence contains invalid characters, or if the regions are
+/// not sorted, overlap or go past the end of the sequence.
+///
+/// # Examples
+///
+/// ```rust
+/// use iirs::{SearchParams, find_irs, find_irs_in_regions};
+///
+/// let seq = "acbbgtttacbbgt".as_bytes();
+/// let params = SearchParams::new(3, 6, 2, 0).unwrap();
+/// let irs = find_irs_in_regions(&params, &seq, &[8..14]).unwrap();
+/// assert_eq!(irs[0].as_tuple(), (8, 13, 0));
+///
+/// let all = find_irs(&params, &seq).unwrap();
+/// let centered = all.into_iter().filter(|ir| ir.left + ir.right >= 16).collect::<Vec<_>>();
+/// assert_eq!(irs, centered);
+/// ```
+pub fn find_irs_in_regions(
+    params: &SearchParams,
+    seq: &[u8],
+    regions: &[Range<usize>],
+) -> Result<Vec<InvertedRepeat>> {
+    let mut irs = Vec::new();
+    find_irs_in_regions_with(params, seq, regions, |ir| {
+        irs.push(ir);
+        Ok(())
+    })?;
+    Ok(irs)
+}
+
+/// Same as [`find_irs_in_regions`], but calling `f` on each IR (in the same order) instead
+/// of collecting them.
+///
+/// Every region is searched as a single window: split them to bound the memory usage.
+pub fn find_irs_in_regions_with<F>(
+    params: &SearchParams,
+    seq: &[u8],
+    regions: &[Range<usize>],
+    f: F,
+) -> Result<()>
+where
+    F: FnMut(InvertedRepeat) -> Result<()>,
+{
+    params.check_supported()?;
+    let seq = utils::sanitize_sequence(seq)?;
+    check_regions(regions, seq.len())?;
+    find_irs_in_cores(params, &seq, regions.iter().cloned(), f)
+}
+
+/// Check that the regions are sorted, do not overlap and are inside `0..n`.
+pub(crate) fn check_regions(regions: &[Range<usize>], n: usize) -> Result<()> {
+    let mut previous_end = 0;
+    for region in regions {
+        if region.start < previous_end || region.start > region.end {
+            bail!(
+                "The regions must be sorted and not overlap, found {}..{} after end {}.",
+                region.start,
+                region.end,
+                previous_end
+            )
+        }
+        if region.end > n {
+            bail!(
+                "The region {}..{} goes past the end of the sequence (length {n}).",
+                region.start,
+                region.end
+            )
+        }
+        previous_end = region.end;
+    }
+    Ok(())
+}
+
+/// Search the sanitized `seq` core by core, passing the IRs to `f` in order.
+///
+/// The cores must be sorted and not overlap.
+fn find_irs_in_cores<F>(
+    params: &SearchParams,
+    seq: &[u8],
+    cores: impl Iterator<Item = Range<usize>>,
+    f: F,
+) -> Result<()>
+where
+    F: FnMut(InvertedRepeat) -> Result<()>,
+{
+    if params.circular && seq.is_empty() {
+        return Ok(());
+    }
+    let context = params
+        .max_len
+        .saturating_add(params.max_gap / 2)
+        .saturating_add(2);
+
+    let mut ordered = OrderedIrs::new(params, f);
+    for core in cores.filter(|core| !core.is_empty()) {
+        let irs = find_irs_in_core(params, seq, core.start, core.end, context)?;
+        ordered.push(irs, 2 * core.end)?;
+    }
+    ordered.finish()
+}
+
+/// Search the sequence as a single window (wrapping around it if it is circular), with the
+/// index needed by `params`.
+pub(crate) fn find_irs_whole(params: &SearchParams, seq: &[u8]) -> Result<Vec<InvertedRepeat>> {
+    find_irs_chunked(params, seq, seq.len().max(1))
+}
+
+/// Same as [`find_irs_whole`], calling `f` on each IR.
+pub(crate) fn find_irs_whole_with<F>(params: &SearchParams, seq: &[u8], f: F) -> Result<()>
+where
+    F: FnMut(InvertedRepeat) -> Result<()>,
+{
+    find_irs_chunked_with(params, seq, seq.len().max(1), f)
+}
+
+/// Find the IRs centered in `core_start..core_end`, with positions relative to `seq`.
+///
+/// The index is built over the core plus `context` positions on each side. If the extension
+/// of some IR reaches the border of the window, the result could depend on the sequence
+/// outside of it, so we retry with twice the context.
+///
+/// If the sequence is circular, the context wraps around its ends, and the IRs are moved
+/// so that `left < seq.len()` (see [`crate::find_irs`]).
+fn find_irs_in_core(
+    params: &SearchParams,
+    seq: &[u8],
+    core_start: usize,
+    core_end: usize,
+    mut context: usize,
+) -> Result<Vec<InvertedRepeat>> {
+    let n = seq.len();
+    loop {
+        let (window_start, window) = if params.circular {
+            circular_window(seq, core_start, core_end, context)
+        } else {
+            let window_start = core_start.saturating_sub(context);
+            let window_end = core_end.saturating_add(context).min(n);
+            (window_start, Cow::Borrowed(&seq[window_start..window_end]))
+        };
+        let window_end = window_start + window.len();
+        // The circular window is shifted, but its core is always after `context` positions
+        let core_offset = if params.circular {
+            context
+        } else {
+            core_start - window_start
+        };
+
+        let index = IrIndex::build_for(&window, params)?;
+        let centers = 2 * core_offset..2 * (core_offset + core_end - core_start);
+        let reached = Reached::default();
+        let mut irs = index.search_centers(params, centers, &reached);
+
+        let reached_start = reached.start.load(Ordering::Relaxed);
+        let reached_end = reached.end.load(Ordering::Relaxed);
+        let (clipped_start, clipped_end) = if params.circular {
+            // There is always more sequence, but once the window goes around the whole
+            // circle on both sides, extending it further is not meaningful.
+            let whole_circle = context >= n;
+            (reached_start && !whole_circle, reached_end && !whole_circle)
+        } else {
+            (
+                reached_start && window_start > 0,
+                reached_end && window_end < n,
+            )
+        };
+
+        if !clipped_start && !clipped_end {
+            for ir in &mut irs {
+                ir.shift(window_start);
+            }
+            if params.circular {
+                unroll_irs(&mut irs, n);
+            }
+            return Ok(irs);
+        }
+
+        context = context.saturating_mul(2);
+    }
+}
+
+/// Window of a circular sequence over `core_start..core_end` with `context` positions on
+/// each side, wrapping around the ends as many times as needed.
+///
+/// Positions are shifted by a multiple of `seq.len()`, so that the start of the window
+/// (the first returned value) is not negative.
+fn circular_window(
+    seq: &[u8],
+    core_start: usize,
+    core_end: usize,
+    context: usize,
+) -> (usize, Cow<'_, [u8]>) {
+    let n = seq.len();
+    let origin = n * context.div_ceil(n);
+    let window_start = origin + core_start - context;
+    let window_len = core_end - core_start + 2 * context;
+    let window = (window_start..window_start + window_len)
+        .map(|pos| seq[pos % n])
+        .collect();
+    (window_start, Cow::Owned(window))
+}
+
+/// Move the IRs found in a circular window so that `left < n`, dropping the ones that
+/// would overlap themselves around the circle.
+fn unroll_irs(irs: &mut Vec<InvertedRepeat>, n: usize) {
+    irs.retain(|ir| ir.right - ir.left < n);
+    for ir in irs {
+        ir.unshift(ir.left - ir.left % n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_irs;
+    use crate::index::{IrIndex, sort_irs};
+    use crate::ir::RepeatKind;
+    use crate::matrix::MatchMode;
+    use crate::report::ReportMode;
+
+    fn assert_same_as_find_irs(params: &SearchParams, seq: &[u8]) {
+        let expected = find_irs(params, seq).unwrap();
+        for window_size in [1, 7, 50, seq.len()] {
+            let received = find_irs_chunked(params, seq, window_size).unwrap();
+            assert_eq!(received, expected, "window_size={window_size}");
+        }
+    }
+
+    #[test]
+    fn test_chunked_iupac() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(5);
+        for (min_len, max_len, max_gap, mismatches) in
+            [(10, 100, 5, 1), (3, 5, 9, 1), (2, 7, 0, 0), (7, 14, 5, 3)]
+        {
+            let params = SearchParams::new(min_len, max_len, max_gap, mismatches).unwrap();
+            assert_same_as_find_irs(&params, seq.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_chunked_match_modes() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(5);
+        for match_mode in [MatchMode::Strict, MatchMode::AmbiguousMismatch] {
+            let mut params = SearchParams::new(3, 10, 5, 1).unwrap();
+            params.match_mode = match_mode;
+            assert_same_as_find_irs(&params, seq.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_chunked_edits() {
+        let seq = b"ttttgcatgcaatcccccgattagcatgcggggacgtnnnnacgtgcatgcaatcccccgattagcatgc";
+        let mut params = SearchParams::new(4, 20, 6, 0).unwrap();
+        params.max_edits = Some(2);
+        assert_same_as_find_irs(&params, seq);
+        params.circular = true;
+        assert_same_as_find_irs(&params, seq);
+    }
+
+    #[test]
+    fn test_chunked_kinds() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(5);
+        for kind in [RepeatKind::Direct, RepeatKind::Mirror] {
+            for (min_len, max_len, max_gap, mismatches) in
+                [(4, 20, 5, 1), (3, 5, 9, 1), (2, 7, 0, 0), (7, 14, 30, 3)]
+            {
+                let mut params = SearchParams::new(min_len, max_len, max_gap, mismatches).unwrap();
+                params.kind = kind;
+                assert_same_as_find_irs(&params, seq.as_bytes());
+                params.circular = true;
+                assert_same_as_find_irs(&params, seq.as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunked_reports() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(5);
+        for report in [ReportMode::Maximal, ReportMode::BestPerCenter] {
+            for kind in [RepeatKind::Inverted, RepeatKind::Direct] {
+                let mut params = SearchParams::new(4, 20, 9, 2).unwrap();
+                params.report = report;
+                params.kind = kind;
+                assert_same_as_find_irs(&params, seq.as_bytes());
+                params.circular = true;
+                assert_same_as_find_irs(&params, seq.as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunked_n_runs() {
+        // Extensions inside the run of n go way beyond max_len.
+        let seq = format!(
+            "acgtacgga{}ttagcatgca{}tgca",
+            "n".repeat(300),
+            "n".repeat(40)
+        );
+        let params = SearchParams::new(4, 6, 9, 3).unwrap();
+        assert_same_as_find_irs(&params, seq.as_bytes());
+    }
+
+    fn circular(min_len: usize, max_len: usize, max_gap: usize, mismatches: usize) -> SearchParams {
+        let mut params = SearchParams::new(min_len, max_len, max_gap, mismatches).unwrap();
+        params.circular = true;
+        params
+    }
+
+    #[test]
+    fn test_circular_wraps_origin() {
+        // The palindrome acgt is split by the origin
+        let params = circular(2, 4, 0, 0);
+        let irs = find_irs(&params, b"gtaaaaac").unwrap();
+        let tuples: Vec<_> = irs.iter().map(InvertedRepeat::as_tuple).collect();
+        assert_eq!(tuples, [(6, 9, 0)]);
+
+        let mut linear = params.clone();
+        linear.circular = false;
+        assert!(find_irs(&linear, b"gtaaaaac").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_circular_same_as_rotations() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(3);
+        let seq = utils::sanitize_sequence(seq.as_bytes()).unwrap();
+        let n = seq.len();
+        let kinds = [RepeatKind::Inverted, RepeatKind::Direct, RepeatKind::Mirror];
+        for (kind, mut params) in kinds.into_iter().flat_map(|kind| {
+            [
+                circular(5, 100, 5, 1),
+                circular(3, 5, 9, 1),
+                circular(7, 14, 5, 3),
+            ]
+            .map(|params| (kind, params))
+        }) {
+            params.kind = kind;
+            let expected = find_irs(&params, &seq).unwrap();
+            assert!(
+                expected
+                    .iter()
+                    .all(|ir| ir.left < n && ir.right < ir.left + n)
+            );
+            assert!(expected.iter().any(|ir| ir.right >= n));
+
+            for rotation in [1, 17, n - 1] {
+                let rotated = [&seq[rotation..], &seq[..rotation]].concat();
+                let mut received = find_irs(&params, &rotated).unwrap();
+                for ir in &mut received {
+                    // Back to the positions of `seq`
+                    ir.shift(rotation);
+                    if ir.left >= n {
+                        ir.unshift(n);
+                    }
+                }
+                sort_irs(&mut received);
+                assert_eq!(received, expected, "{kind} rotation={rotation}");
+            }
+
+            for window_size in [1, 7, 50] {
+                let received = find_irs_chunked(&params, &seq, window_size).unwrap();
+                assert_eq!(received, expected, "{kind} window_size={window_size}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunked_invalid_window_size() {
+        let params = SearchParams::default();
+        assert!(find_irs_chunked(&params, b"acgt", 0).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_regions_same_as_filtered_find_irs() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(5);
+        let seq = seq.as_bytes();
+        let n = utils::sanitize_sequence(seq).unwrap().len();
+        let regions = [
+            vec![0..n],
+            vec![3..4, 10..60, 60..61, 100..n],
+            vec![],
+            vec![n..n],
+        ];
+        let kinds = [RepeatKind::Inverted, RepeatKind::Direct, RepeatKind::Mirror];
+        for (circular, kind) in [false, true]
+            .into_iter()
+            .flat_map(|c| kinds.map(|k| (c, k)))
+        {
+            let mut params = SearchParams::new(3, 10, 5, 1).unwrap();
+            params.circular = circular;
+            params.kind = kind;
+            let all = find_irs(&params, seq).unwrap();
+            let index = IrIndex::build(seq).unwrap();
+            for regions in &regions {
+                let expected: Vec<_> = all
+                    .iter()
+                    .filter(|ir| {
+                        let center = if circular {
+                            (ir.left + ir.right) % (2 * n)
+                        } else {
+                            ir.left + ir.right
+                        };
+                        regions
+                            .iter()
+                            .any(|region| (2 * region.start..2 * region.end).contains(&center))
+                    })
+                    .cloned()
+                    .collect();
+                let received = find_irs_in_regions(&params, seq, regions).unwrap();
+                assert_eq!(
+                    received, expected,
+                    "circular={circular}, {kind}, {regions:?}"
+                );
+
+                let mut received = Vec::new();
+                index
+                    .search_regions_with(&params, regions, |ir| {
+                        received.push(ir);
+                        Ok(())
+                    })
+                    .unwrap();
+                assert_eq!(
+                    received, expected,
+                    "index, circular={circular}, {kind}, {regions:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_regions_invalid() {
+        let params = SearchParams::default();
+        let seq = b"acgtacgt";
+        assert!(find_irs_in_regions(&params, seq, &[0..9]).is_err());
+        assert!(find_irs_in_regions(&params, seq, &[4..6, 2..3]).is_err());
+        assert!(find_irs_in_regions(&params, seq, &[0..4, 3..6]).is_err());
+        assert!(find_irs_in_regions(&params, seq, &[0..4, 4..8]).is_ok());
+    }
+}
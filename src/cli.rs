@@ -1,27 +1,49 @@
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Result, bail};
+use clap::{Args, Parser, Subcommand};
 
 use crate::config::{Config, SearchParams};
 use crate::constants::{
-    DEFAULT_INPUT_FILE, DEFAULT_MAX_GAP, DEFAULT_MAX_LEN, DEFAULT_MIN_LEN, DEFAULT_MISMATCHES,
-    DEFAULT_OUTPUT_FILE, DEFAULT_SEQ_NAME, OutputFormat,
+    DEFAULT_INDEX_FILE, DEFAULT_INPUT_FILE, DEFAULT_MAX_GAP, DEFAULT_MAX_LEN, DEFAULT_MIN_LEN,
+    DEFAULT_MISMATCHES, DEFAULT_OUTPUT_FILE, DEFAULT_SEQ_NAME, IUPAC_SYMBOLS, OutputFormat,
+    STDOUT_PATH,
 };
-use crate::utils::safe_extract_records;
-use seq_io::fasta::{OwnedRecord, Record};
+use crate::input::{ReadOptions, SeqRecord, extract_records, read_bed_regions};
+use crate::ir::RepeatKind;
+use crate::matrix::{MatchMode, NPolicy};
+use crate::report::ReportMode;
+use crate::score::ScoreWeights;
+use crate::utils::{InvalidPolicy, ValidationReport};
 
 #[derive(Parser, Debug)]
-#[command(version)]
+#[command(version, args_conflicts_with_subcommands = true)]
 pub struct Cli {
-    /// Input filename (FASTA)
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Input filename (FASTA, FASTQ, GenBank or EMBL, possibly compressed with gzip, bgzip or zstd). `-` for stdin
     #[arg(long, short = 'f', default_value_t = String::from(DEFAULT_INPUT_FILE))]
     pub input_file: String,
 
-    /// Input sequence names (ids), or `ALL_SEQUENCES`.
+    /// Input sequence names (ids, or accessions for GenBank and EMBL), or `ALL_SEQUENCES`.
     #[arg(long, short, default_value = DEFAULT_SEQ_NAME, value_delimiter = ' ')]
     pub seq_names: Vec<String>,
 
+    /// FASTQ only: mask as `n` the bases with a (Phred+33) quality below this one
+    #[arg(long)]
+    pub min_quality: Option<u8>,
+
+    /// Skip the sequences with characters that are not IUPAC symbols, instead of failing
+    #[arg(long, default_value_t = false, conflicts_with = "replace_invalid_with")]
+    pub skip_invalid_records: bool,
+
+    /// Replace the characters that are not IUPAC symbols with this one (usually `n`),
+    /// instead of failing
+    #[arg(long, value_parser = parse_iupac_symbol)]
+    pub replace_invalid_with: Option<u8>,
+
     /// Minimum length
     #[arg(long, short, default_value_t = DEFAULT_MIN_LEN)]
     pub min_len: usize,
@@ -38,7 +60,91 @@ pub struct Cli {
     #[arg(long, short = 'x', default_value_t = DEFAULT_MISMATCHES)]
     pub mismatches: usize,
 
-    /// Output filename for a single sequence. Output directory for multiple
+    /// Allow insertions and deletions between the arms: at most this many edits (mismatches
+    /// and unpaired nucleotides), instead of `--mismatches`. The alignment of the arms is
+    /// reported as a CIGAR string
+    #[arg(long, conflicts_with = "mismatches")]
+    pub max_edits: Option<usize>,
+
+    /// The repeats to find: `inverted` (the right arm is the reverse complement of the
+    /// left one), `direct` (a copy of it, in tandem or after a spacer of at most
+    /// `--max-gap`) or `mirror` (its reverse, without complement)
+    #[arg(long, default_value = "inverted")]
+    pub kind: RepeatKind,
+
+    /// Which of the IRs sharing a center to report: `all`, `maximal` (those whose arms are
+    /// not contained in the arms of another one) or `best-per-center` (the one with more
+    /// matching nucleotides)
+    #[arg(long, default_value = "all")]
+    pub report: ReportMode,
+
+    /// How IUPAC codes pair: `permissive` (sharing a base, as IUPACpal), `strict` (only
+    /// unambiguous Watson-Crick pairs, ambiguous codes end the arms), `ambiguous-mismatch`
+    /// (ambiguous codes count as mismatches), or the path of a custom match matrix file
+    #[arg(long, default_value = "permissive")]
+    pub match_mode: MatchMode,
+
+    /// RNA mode: G·U wobble pairs also match (marked `:` in the classic output and `w`
+    /// in the others), and the sequences are written with `u`
+    #[arg(long, default_value_t = false)]
+    pub rna: bool,
+
+    /// How the `n` codes pair, whatever the match mode: `match-all` (as the match mode),
+    /// `never-match` (a mismatch) or `break` (ends the arms). The last two stop the runs
+    /// of `n` of assembly gaps from producing IRs
+    #[arg(long, default_value = "match-all")]
+    pub n_policy: NPolicy,
+
+    /// Treat the sequences as circular (plasmids, organelles) to also find the IRs wrapping
+    /// around their origin. GenBank and EMBL records with a circular topology always are
+    #[arg(long, default_value_t = false)]
+    pub circular: bool,
+
+    /// Only report the IRs centered at or after this 1-based position
+    #[arg(long, conflicts_with = "regions")]
+    pub start: Option<usize>,
+
+    /// Only report the IRs centered at or before this 1-based position
+    #[arg(long, conflicts_with = "regions")]
+    pub end: Option<usize>,
+
+    /// BED file of the regions to scan: only the IRs centered in them are reported, with their
+    /// arms extending outside if needed. The sequences without regions are skipped
+    #[arg(long)]
+    pub regions: Option<String>,
+
+    /// Add the fraction of the arms that is soft-masked (in lowercase in the input, as done
+    /// by RepeatMasker) to the csv and json outputs
+    #[arg(long, default_value_t = false)]
+    pub soft_mask: bool,
+
+    /// Skip the IRs whose arms are soft-masked above this fraction (between 0 and 1).
+    /// Sequences written in a single case are not masked
+    #[arg(long, value_parser = parse_fraction)]
+    pub max_masked_fraction: Option<f64>,
+
+    /// Add the score of every IR (see `--score-weights`) to the csv and json outputs. The
+    /// bed outputs use it as their score, rounded and clamped to 0..=1000
+    #[arg(long, default_value_t = false)]
+    pub score: bool,
+
+    /// Weights of the score of the IRs, as `key=weight` separated by commas: `match` and
+    /// `mismatch` (per pair of the arms), `indel` (per unpaired nucleotide), `ambiguous`
+    /// (per ambiguous IUPAC code of the arms) and `gap` (per nucleotide of the loop)
+    #[arg(long, default_value_t)]
+    pub score_weights: ScoreWeights,
+
+    /// Skip the IRs scoring below this
+    #[arg(long, allow_negative_numbers = true)]
+    pub min_score: Option<f64>,
+
+    /// Write the IRs from the highest score to the lowest. They are collected in memory
+    /// first
+    #[arg(long, default_value_t = false)]
+    pub sort_by_score: bool,
+
+    /// Output filename for a single sequence. Output directory for multiple.
+    /// `-` writes every sequence to stdout (a single one for json, use jsonl instead)
     #[arg(long, short, default_value = DEFAULT_OUTPUT_FILE)]
     pub output_path: PathBuf,
 
@@ -46,49 +152,264 @@ pub struct Cli {
     #[arg(long, short = 'F', default_value_t, value_enum)]
     pub output_format: OutputFormat,
 
+    /// Index filename (built with `iirs index`) for a single sequence. Index directory for multiple.
+    /// The index is memory mapped instead of being computed. Only for linear sequences, and
+    /// inverted or direct repeats
+    #[arg(long)]
+    pub index: Option<PathBuf>,
+
+    /// Scan the sequences in windows of this size to bound the memory usage.
+    /// The results are the same as scanning the whole sequences
+    #[arg(long, conflicts_with = "index")]
+    pub window_size: Option<usize>,
+
     /// Suppresses non-essential output when enabled
     #[arg(long, short, default_value_t = false)]
     pub quiet: bool,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Build the index of the input sequences and write it to disk
+    Index(IndexArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct IndexArgs {
+    /// Input filename (FASTA, FASTQ, GenBank or EMBL, possibly compressed with gzip, bgzip or zstd). `-` for stdin
+    #[arg(long, short = 'f', default_value_t = String::from(DEFAULT_INPUT_FILE))]
+    pub input_file: String,
+
+    /// Input sequence names (ids, or accessions for GenBank and EMBL), or `ALL_SEQUENCES`.
+    #[arg(long, short, default_value = DEFAULT_SEQ_NAME, value_delimiter = ' ')]
+    pub seq_names: Vec<String>,
+
+    /// FASTQ only: mask as `n` the bases with a (Phred+33) quality below this one
+    #[arg(long)]
+    pub min_quality: Option<u8>,
+
+    /// Skip the sequences with characters that are not IUPAC symbols, instead of failing
+    #[arg(long, default_value_t = false, conflicts_with = "replace_invalid_with")]
+    pub skip_invalid_records: bool,
+
+    /// Replace the characters that are not IUPAC symbols with this one (usually `n`),
+    /// instead of failing
+    #[arg(long, value_parser = parse_iupac_symbol)]
+    pub replace_invalid_with: Option<u8>,
+
+    /// Index filename for a single sequence. Index directory for multiple
+    #[arg(long, short, default_value = DEFAULT_INDEX_FILE)]
+    pub output_path: PathBuf,
+
+    /// Suppresses non-essential output when enabled
+    #[arg(long, short, default_value_t = false)]
+    pub quiet: bool,
+}
+
+impl IndexArgs {
+    /// Return a vector of pairs `(index path, SeqRecord)` from the CLI arguments.
+    pub fn try_from_args(&self) -> Result<Vec<(PathBuf, SeqRecord)>> {
+        if self.output_path == Path::new(STDOUT_PATH) {
+            bail!("The index can not be written to stdout, it has to be memory mapped from a file.")
+        }
+        let options = read_options(
+            self.min_quality,
+            self.skip_invalid_records,
+            self.replace_invalid_with,
+        );
+        let extracted = extract_records(&self.input_file, &self.seq_names, &options)?;
+        if !self.quiet {
+            warn_invalid(&extracted.invalid, &options);
+        }
+        let records = extracted.records;
+        let only_one_sequence_found = records.len() == 1;
+        Ok(records
+            .into_iter()
+            .map(|record| {
+                let path =
+                    per_sequence_path(&self.output_path, &record.id, only_one_sequence_found);
+                (path, record)
+            })
+            .collect())
+    }
+}
+
+/// The [`ReadOptions`] of the input arguments, shared by the search and the index.
+fn read_options(
+    min_quality: Option<u8>,
+    skip_invalid_records: bool,
+    replace_invalid_with: Option<u8>,
+) -> ReadOptions {
+    let on_invalid = match (skip_invalid_records, replace_invalid_with) {
+        (true, _) => InvalidPolicy::SkipRecord,
+        (false, Some(replacement)) => InvalidPolicy::Replace(replacement),
+        (false, None) => InvalidPolicy::Error,
+    };
+    ReadOptions {
+        min_quality,
+        on_invalid,
+    }
+}
+
+/// Tell on stderr which sequences were skipped or repaired because of invalid characters.
+fn warn_invalid(invalid: &[(String, ValidationReport)], options: &ReadOptions) {
+    for (id, report) in invalid {
+        match options.on_invalid {
+            InvalidPolicy::Replace(replacement) => eprintln!(
+                "Replaced with '{}' the invalid characters of sequence '{id}': it {report}.",
+                replacement as char
+            ),
+            _ => eprintln!("Skipped sequence '{id}': it {report}."),
+        }
+    }
+}
+
+fn parse_iupac_symbol(arg: &str) -> Result<u8, String> {
+    match arg.to_ascii_lowercase().as_bytes() {
+        [byte] if IUPAC_SYMBOLS.contains(*byte as char) => Ok(*byte),
+        _ => Err(format!("'{arg}' is not an IUPAC symbol")),
+    }
+}
+
+fn parse_fraction(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(format!("'{arg}' is not a fraction between 0 and 1")),
+    }
+}
+
+/// Path for the file of a sequence: `path` itself if there is only one sequence (or if it
+/// is stdout), otherwise `path` is treated as a directory.
+fn per_sequence_path(path: &Path, seq_name: &str, only_one_sequence_found: bool) -> PathBuf {
+    if only_one_sequence_found || path == Path::new(STDOUT_PATH) {
+        path.to_path_buf()
+    } else {
+        path.join(seq_name)
+    }
+}
+
 impl Cli {
     pub fn parse_args() -> Self {
         Self::parse()
     }
 
-    /// Return a vector of pairs `(Config, OwnedRecord)` from the CLI arguments.
+    /// Return a vector of pairs `(Config, SeqRecord)` from the CLI arguments.
     ///
     /// The `check_bounds` argument determines if bound checking has to be performed for
     /// every sequence.
     ///
     /// The `Config` is different for every sequence since it contains the sequence name (id)
-    /// and the output file. The `SearchParams` do not change.
-    pub fn try_from_args(&self, check_bounds: bool) -> Result<Vec<(Config<'_>, OwnedRecord)>> {
-        let params = SearchParams::new(self.min_len, self.max_len, self.max_gap, self.mismatches)?;
-        let records = safe_extract_records(&self.input_file, &self.seq_names)?;
+    /// and the output file. The `SearchParams` only change with the topology of the sequence.
+    pub fn try_from_args(&self, check_bounds: bool) -> Result<Vec<(Config<'_>, SeqRecord)>> {
+        let mut params =
+            SearchParams::new(self.min_len, self.max_len, self.max_gap, self.mismatches)?;
+        params.match_mode = self.match_mode.clone();
+        params.rna = self.rna;
+        params.n_policy = self.n_policy;
+        params.kind = self.kind;
+        params.report = self.report;
+        if self.kind != RepeatKind::Inverted && self.rna {
+            bail!(
+                "--rna only applies to inverted repeats, not to {} ones.",
+                self.kind
+            )
+        }
+        if self.kind == RepeatKind::Direct && self.max_edits.is_some() {
+            bail!("--max-edits is not supported for direct repeats.")
+        }
+        // The index is that of the linear sequence and its reverse complement
+        if self.index.is_some() {
+            if self.window_size.is_some() {
+                bail!("--window-size can not be used with --index, which scans whole sequences.")
+            }
+            if self.circular {
+                bail!("--index does not support circular sequences.")
+            }
+            if self.kind == RepeatKind::Mirror {
+                bail!("--index does not support mirror repeats.")
+            }
+        }
+        if let Some(max_edits) = self.max_edits {
+            if max_edits >= self.min_len {
+                bail!(
+                    "max_edits={max_edits} must be less than min_len={}.",
+                    self.min_len
+                )
+            }
+            params.max_edits = Some(max_edits);
+        }
+        let options = read_options(
+            self.min_quality,
+            self.skip_invalid_records,
+            self.replace_invalid_with,
+        );
+        let extracted = extract_records(&self.input_file, &self.seq_names, &options)?;
+        if !self.quiet {
+            warn_invalid(&extracted.invalid, &options);
+        }
+        let records = extracted.records;
+        let mut bed_regions = self.regions.as_deref().map(read_bed_regions).transpose()?;
         let only_one_sequence_found = records.len() == 1;
         let mut config_record_pairs = Vec::new();
 
         for record in records {
+            let regions = match &mut bed_regions {
+                Some(bed_regions) => match bed_regions.remove(&record.id) {
+                    Some(regions) => Some(regions),
+                    None => continue,
+                },
+                None => self.range(record.seq.len())?,
+            };
+            if let Some(region) = regions.as_ref().and_then(|regions| regions.last())
+                && region.end > record.seq.len()
+            {
+                bail!(
+                    "The region {}..{} goes past the end of sequence '{}' (length {}).",
+                    region.start,
+                    region.end,
+                    record.id,
+                    record.seq.len()
+                )
+            }
+
             // I don't really like this leak hack to preserve the references
             // but the alternative of making everything a String is even worse.
 
             // IUPACpal convention is to always use IUPACpal.out no matter the sequence name.
             // In order to ease the validity checks, we keep that convention if the input consists
             // of only one sequence.
-            let seq_name = String::from(record.id()?);
-            let output_path: PathBuf = if only_one_sequence_found {
-                self.output_path.clone()
-            } else {
-                self.output_path.join(&seq_name)
-            };
+            let seq_name = record.id.clone();
+            let output_path =
+                per_sequence_path(&self.output_path, &seq_name, only_one_sequence_found);
+            let index_path = self
+                .index
+                .as_deref()
+                .map(|path| per_sequence_path(path, &seq_name, only_one_sequence_found));
+
+            let mut params = params.clone();
+            params.circular = self.circular || record.circular;
+            if params.circular && self.index.is_some() {
+                bail!(
+                    "Sequence '{}' is circular, which --index does not support.",
+                    record.id
+                )
+            }
 
             let config = Config {
                 input_file: &self.input_file,
                 seq_name: Box::leak(seq_name.into_boxed_str()),
-                params: params.clone(),
+                params,
                 output_path,
                 output_format: self.output_format.clone(),
+                index_path,
+                window_size: self.window_size,
+                regions,
+                soft_mask: self.soft_mask,
+                max_masked_fraction: self.max_masked_fraction,
+                score: self.score,
+                score_weights: self.score_weights,
+                min_score: self.min_score,
+                sort_by_score: self.sort_by_score,
             };
 
             if check_bounds {
@@ -97,6 +418,31 @@ impl Cli {
             config_record_pairs.push((config, record));
         }
 
+        // Every sequence is a json document: one after the other, they are not valid json
+        if self.output_path == Path::new(STDOUT_PATH)
+            && self.output_format == OutputFormat::Json
+            && config_record_pairs.len() > 1
+        {
+            bail!(
+                "The json output of several sequences can not be written to stdout: use -F jsonl, or an output directory."
+            )
+        }
+
         Ok(config_record_pairs)
     }
+
+    /// The region given by `--start` and `--end` (1-based, inclusive) of a sequence of
+    /// length `n`, if any.
+    fn range(&self, n: usize) -> Result<Option<Vec<Range<usize>>>> {
+        if self.start.is_none() && self.end.is_none() {
+            return Ok(None);
+        }
+        let start = self.start.unwrap_or(1);
+        let end = self.end.unwrap_or(n);
+        if start == 0 || start > end || end > n {
+            bail!("Invalid range: start={start}, end={end} for a sequence of length {n}.")
+        }
+        let region = start - 1..end;
+        Ok(Some(vec![region]))
+    }
 }
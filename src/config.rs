@@ -1,10 +1,15 @@
 use anyhow::{Result, bail};
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 use crate::constants::{
     DEFAULT_INPUT_FILE, DEFAULT_MAX_GAP, DEFAULT_MAX_LEN, DEFAULT_MIN_LEN, DEFAULT_MISMATCHES,
-    DEFAULT_OUTPUT_FILE, DEFAULT_SEQ_NAME, OutputFormat,
+    DEFAULT_OUTPUT_FILE, DEFAULT_SEQ_NAME, OutputFormat, STDOUT_PATH,
 };
+use crate::ir::RepeatKind;
+use crate::matrix::{MatchMatrix, MatchMode, NPolicy};
+use crate::report::ReportMode;
+use crate::score::ScoreWeights;
 
 #[derive(Debug, Clone)]
 pub struct SearchParams {
@@ -12,6 +17,25 @@ pub struct SearchParams {
     pub max_len: usize,
     pub max_gap: usize,
     pub mismatches: usize,
+    /// Treat the sequence as circular, to also find the IRs wrapping around its origin
+    /// (see [`crate::find_irs`]).
+    pub circular: bool,
+    /// How the IUPAC characters of the arms are paired.
+    pub match_mode: MatchMode,
+    /// RNA mode: the G·U wobble pairs also match, and the sequences are written with `u`.
+    pub rna: bool,
+    /// How the `n` codes pair, overriding the match mode.
+    pub n_policy: NPolicy,
+    /// Allow indels between the arms: the mismatches and the unpaired nucleotides count
+    /// together as edits, up to this number, instead of `mismatches`. The arms can then
+    /// have different lengths (see [`crate::ArmAlignment`]).
+    pub max_edits: Option<usize>,
+    /// The kind of repeats to find: inverted (the default), direct or mirror. The search
+    /// of direct repeats does not support indels: it fails if `max_edits` is set.
+    pub kind: RepeatKind,
+    /// Which of the IRs sharing a center are reported: all of them (the default), the
+    /// maximal ones or the best one.
+    pub report: ReportMode,
 }
 
 impl SearchParams {
@@ -31,9 +55,36 @@ impl SearchParams {
             max_len,
             max_gap,
             mismatches,
+            circular: false,
+            match_mode: MatchMode::default(),
+            rna: false,
+            n_policy: NPolicy::default(),
+            max_edits: None,
+            kind: RepeatKind::default(),
+            report: ReportMode::default(),
         })
     }
 
+    /// The matrix comparing the characters of the arms: the one of the match mode, with
+    /// the wobble pairs in RNA mode (only for inverted repeats), and the `n` paired
+    /// according to the n policy.
+    pub fn matrix(&self) -> MatchMatrix {
+        let mut matrix = self.match_mode.matrix();
+        if self.rna && self.kind == RepeatKind::Inverted {
+            matrix = matrix.with_wobble(self.match_mode == MatchMode::Permissive);
+        }
+        matrix.with_n_policy(self.n_policy)
+    }
+
+    /// Returns an error for the combinations of parameters that the search does not
+    /// support: indels (`max_edits`) between the arms of direct repeats.
+    pub fn check_supported(&self) -> Result<()> {
+        if self.kind == RepeatKind::Direct && self.max_edits.is_some() {
+            bail!("max_edits is not supported for direct repeats.")
+        }
+        Ok(())
+    }
+
     // Note that if max_gap >= n, the result is the same as if it was equal to n.
     pub fn check_bounds(&self, n: usize) -> Result<()> {
         if self.min_len >= n {
@@ -74,6 +125,28 @@ pub struct Config<'a> {
     pub params: SearchParams,
     pub output_path: PathBuf,
     pub output_format: OutputFormat,
+    /// Path of a prebuilt index for the sequence (see [`crate::IrIndex::load`]).
+    pub index_path: Option<PathBuf>,
+    /// Scan the sequence in windows of this size (see [`crate::find_irs_chunked`]).
+    pub window_size: Option<usize>,
+    /// Only report the IRs centered in these sorted, non overlapping regions of the
+    /// sequence (see [`crate::find_irs_in_regions`]). The whole sequence if `None`.
+    pub regions: Option<Vec<Range<usize>>>,
+    /// Add the fraction of the arms that is soft-masked (see [`crate::SoftMask`]) to the
+    /// csv and json outputs.
+    pub soft_mask: bool,
+    /// Skip the IRs whose arms are soft-masked above this fraction.
+    pub max_masked_fraction: Option<f64>,
+    /// Add the score of every IR to the csv and json outputs, and use it as the score of
+    /// the bed outputs.
+    pub score: bool,
+    /// The weights of the score (see [`ScoreWeights`]).
+    pub score_weights: ScoreWeights,
+    /// Skip the IRs scoring below this.
+    pub min_score: Option<f64>,
+    /// Write the IRs from the highest score to the lowest, instead of by position. They
+    /// have to be collected first.
+    pub sort_by_score: bool,
 }
 
 impl<'a> Config<'a> {
@@ -95,8 +168,32 @@ impl<'a> Config<'a> {
             params,
             output_path: output_path.into(),
             output_format,
+            index_path: None,
+            window_size: None,
+            regions: None,
+            soft_mask: false,
+            max_masked_fraction: None,
+            score: false,
+            score_weights: ScoreWeights::default(),
+            min_score: None,
+            sort_by_score: false,
         })
     }
+
+    /// The 1-based, inclusive range of the sequence of length `n` that is scanned: from the
+    /// start of the first region to the end of the last one.
+    pub fn scanned_range(&self, n: usize) -> (usize, usize) {
+        match self.regions.as_deref() {
+            Some([first, .., last]) => (first.start + 1, last.end),
+            Some([region]) => (region.start + 1, region.end),
+            _ => (1, n),
+        }
+    }
+
+    /// Whether the IRs are written to the standard output (`-` as output path).
+    pub fn writes_to_stdout(&self) -> bool {
+        self.output_path == Path::new(STDOUT_PATH)
+    }
 }
 
 impl Default for Config<'_> {
@@ -107,6 +204,15 @@ impl Default for Config<'_> {
             params: SearchParams::default(),
             output_path: PathBuf::from(DEFAULT_OUTPUT_FILE),
             output_format: OutputFormat::default(),
+            index_path: None,
+            window_size: None,
+            regions: None,
+            soft_mask: false,
+            max_masked_fraction: None,
+            score: false,
+            score_weights: ScoreWeights::default(),
+            min_score: None,
+            sort_by_score: false,
         }
     }
 }
@@ -119,8 +225,50 @@ impl std::fmt::Display for Config<'_> {
         writeln!(f, "max_len:     {}", self.params.max_len)?;
         writeln!(f, "max_gap:     {}", self.params.max_gap)?;
         writeln!(f, "mismatches:  {}", self.params.mismatches)?;
+        if let Some(max_edits) = self.params.max_edits {
+            writeln!(f, "max_edits:   {max_edits}")?;
+        }
+        if self.params.kind != RepeatKind::Inverted {
+            writeln!(f, "kind:        {}", self.params.kind)?;
+        }
+        if self.params.report != ReportMode::All {
+            writeln!(f, "report:      {}", self.params.report)?;
+        }
+        if self.params.circular {
+            writeln!(f, "circular:    true")?;
+        }
+        if self.params.rna {
+            writeln!(f, "rna:         true")?;
+        }
+        if self.params.match_mode != MatchMode::Permissive {
+            writeln!(f, "match_mode:  {}", self.params.match_mode)?;
+        }
+        if self.params.n_policy != NPolicy::MatchAll {
+            writeln!(f, "n_policy:    {}", self.params.n_policy)?;
+        }
+        if let Some(max_masked_fraction) = self.max_masked_fraction {
+            writeln!(f, "max_masked:  {max_masked_fraction}")?;
+        }
+        if self.score || self.min_score.is_some() || self.sort_by_score {
+            writeln!(f, "score:       {}", self.score_weights)?;
+        }
+        if let Some(min_score) = self.min_score {
+            writeln!(f, "min_score:   {min_score}")?;
+        }
+        if self.sort_by_score {
+            writeln!(f, "sorted_by:   score")?;
+        }
         writeln!(f, "output_path: {}", self.output_path.display())?;
         writeln!(f, "output_fmt:  {}", self.output_format)?;
+        if let Some(index_path) = &self.index_path {
+            writeln!(f, "index_path:  {}", index_path.display())?;
+        }
+        if let Some(window_size) = self.window_size {
+            writeln!(f, "window_size: {window_size}")?;
+        }
+        if let Some(regions) = &self.regions {
+            writeln!(f, "regions:     {}", regions.len())?;
+        }
         Ok(())
     }
 }
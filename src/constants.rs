@@ -8,6 +8,9 @@ pub const DEFAULT_MISMATCHES: usize = 0;
 pub const DEFAULT_INPUT_FILE: &str = "input.fasta";
 pub const DEFAULT_SEQ_NAME: &str = "seq0";
 pub const DEFAULT_OUTPUT_FILE: &str = "iirs.out";
+pub const DEFAULT_INDEX_FILE: &str = "iirs.idx";
+/// Output path that writes to the standard output, for every sequence.
+pub const STDOUT_PATH: &str = "-";
 
 #[derive(clap::ValueEnum, Debug, Clone, Default, PartialEq, Eq)]
 pub enum OutputFormat {
@@ -15,6 +18,16 @@ pub enum OutputFormat {
     Classic,
     Csv,
     Custom,
+    /// A single document with the run configuration, sequence metadata and the IRs.
+    Json,
+    /// One IR object per line.
+    Jsonl,
+    /// BED6, one feature per IR (for genome browsers).
+    Bed,
+    /// BED12, with the arms as blocks and the loop between them.
+    Bed12,
+    /// GFF3, with a parent feature per IR and child features for the arms and the loop.
+    Gff3,
 }
 
 impl std::fmt::Display for OutputFormat {
@@ -23,6 +36,11 @@ impl std::fmt::Display for OutputFormat {
             Self::Classic => "classic",
             Self::Csv => "csv",
             Self::Custom => "custom",
+            Self::Json => "json",
+            Self::Jsonl => "jsonl",
+            Self::Bed => "bed",
+            Self::Bed12 => "bed12",
+            Self::Gff3 => "gff3",
         };
         write!(f, "{fmted}")
     }
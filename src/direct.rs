@@ -0,0 +1,163 @@
+use rmq::Rmq;
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+
+use crate::algo::Reached;
+use crate::config::SearchParams;
+use crate::ir::InvertedRepeat;
+use crate::matrix::MatchMatrix;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Find all the direct repeats whose (doubled) center `left + right` is in `centers`.
+///
+/// The arms of a direct repeat pair `seq[left + k]` with `seq[left + d + k]`, where `d` is
+/// the distance between their starts. Every distance is a diagonal of the first half of `s`,
+/// whose mismatching pairs are found with the Kangaroo method (as in `real_lce_mismatches`).
+///
+/// As for the IRs, the arms are extended from every pair that matches right after one that
+/// does not (or at the start of the sequence), as far as the mismatches allow. They are
+/// then truncated to `max_len`, and to `d` so that they do not overlap: the gap is what
+/// remains of `d`.
+///
+/// The repeats are returned in order of increasing center, filtered by the report mode of
+/// `params`.
+pub(crate) fn add_direct_repeats_in_centers<R: Rmq + Sync>(
+    s: &[u8],
+    inv_sa: &[usize],
+    rmq: &R,
+    params: &SearchParams,
+    matrix: &MatchMatrix,
+    centers: Range<usize>,
+    reached: &Reached,
+) -> Vec<InvertedRepeat> {
+    let n = s.len() / 2 - 1;
+    // The arms are at least min_len long, with at most max_gap between them
+    let max_distance = params.max_len.saturating_add(params.max_gap);
+    let distances = params.min_len..max_distance.saturating_add(1).min(n);
+
+    #[cfg(feature = "parallel")]
+    let mut result: Vec<_> = distances
+        .into_par_iter()
+        .flat_map(|d| add_direct_repeats_at(s, inv_sa, rmq, params, matrix, &centers, reached, d))
+        .collect();
+
+    #[cfg(not(feature = "parallel"))]
+    let mut result: Vec<_> = distances
+        .flat_map(|d| add_direct_repeats_at(s, inv_sa, rmq, params, matrix, &centers, reached, d))
+        .collect();
+
+    // Stable, so that the order does not depend on the parallelism
+    result.sort_by_key(|ir| (ir.left + ir.right, ir.gap));
+    params.report.select(result)
+}
+
+/// Find the direct repeats centered in `centers` whose arms start `d` positions apart.
+#[allow(clippy::too_many_arguments)]
+fn add_direct_repeats_at<R: Rmq>(
+    s: &[u8],
+    inv_sa: &[usize],
+    rmq: &R,
+    params: &SearchParams,
+    matrix: &MatchMatrix,
+    centers: &Range<usize>,
+    reached: &Reached,
+    d: usize,
+) -> Vec<InvertedRepeat> {
+    let n = s.len() / 2 - 1;
+    let mut repeats = Vec::new();
+
+    let max_arm = d.min(params.max_len);
+    let min_arm = params.min_len.max(d.saturating_sub(params.max_gap));
+    if min_arm > max_arm || d + min_arm > n {
+        return repeats;
+    }
+
+    // A repeat starting at `a` is centered at `2 * a + d + arm_len - 1`
+    let first = (centers.start + 1).saturating_sub(d + max_arm).div_ceil(2);
+    let last = (centers.end + 1)
+        .saturating_sub(d + min_arm)
+        .div_ceil(2)
+        .min(n + 1 - d - min_arm);
+    if first >= last {
+        return repeats;
+    }
+
+    // The mismatching pairs, from the one before the first start to the furthest one that
+    // the arms of the last start can reach.
+    let wanted_end = last - 1 + max_arm;
+    let walk_end = wanted_end.min(n - d);
+    if wanted_end > walk_end {
+        reached.end.store(true, Ordering::Relaxed);
+    }
+    let mut mismatches = Vec::new();
+    let mut pos = first.saturating_sub(1);
+    while pos < walk_end {
+        // Most diagonals are made of short runs: only jump over the equal ones
+        if s[pos] == s[pos + d] {
+            let (ii, jj) = (inv_sa[pos], inv_sa[pos + d]);
+            let lce = rmq.rmq(ii.min(jj) + 1, ii.max(jj) + 1).unwrap_or(0);
+            // The characters are equal, but they do not necessarily match
+            pos += matrix.matching_prefix(&s[pos..pos + lce.min(walk_end - pos)]);
+            if pos == walk_end {
+                break;
+            }
+        }
+        if !matrix.match_u8(s[pos], s[pos + d]) {
+            mismatches.push(pos);
+        }
+        pos += 1;
+    }
+
+    // The arms start after a mismatch, or at the start of the sequence. Every start comes
+    // with the index of the first mismatch that could be in the arms.
+    let at_origin = (first == 0).then_some((0, 0));
+    if at_origin.is_some() {
+        reached.start.store(true, Ordering::Relaxed);
+    }
+    let after_mismatches = mismatches
+        .iter()
+        .enumerate()
+        .map(|(idx, &p)| (p + 1, idx + 1));
+    for (a, idx) in at_origin.into_iter().chain(after_mismatches) {
+        if a < first || mismatches.get(idx) == Some(&a) {
+            continue;
+        }
+        if a >= last {
+            break;
+        }
+
+        // Extend the arms up to the first mismatch over the budget
+        let mut end = (a + max_arm).min(walk_end);
+        for (used, &p) in mismatches[idx..].iter().enumerate() {
+            if p >= end {
+                break;
+            }
+            if used == params.mismatches || matrix.breaks_u8(s[p], s[p + d]) {
+                end = p;
+                break;
+            }
+        }
+        // The arms end with a matching pair
+        let mut inner = mismatches[idx..].partition_point(|&p| p < end);
+        while inner > 0 && mismatches[idx + inner - 1] == end - 1 {
+            end -= 1;
+            inner -= 1;
+        }
+
+        let arm_len = end - a;
+        if arm_len < min_arm || !centers.contains(&(2 * a + d + arm_len - 1)) {
+            continue;
+        }
+        repeats.push(InvertedRepeat {
+            left: a,
+            right: a + d + arm_len - 1,
+            gap: d - arm_len,
+            mismatch_positions: mismatches[idx..idx + inner].to_vec(),
+            alignment: None,
+        });
+    }
+
+    repeats
+}
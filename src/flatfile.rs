@@ -0,0 +1,468 @@
+use anyhow::{Result, bail};
+use std::io::BufRead;
+use std::ops::Range;
+
+use crate::input::SeqRecord;
+
+/// A feature annotation of a GenBank / EMBL record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feature {
+    /// Feature key, for example `gene`, `CDS` or `misc_feature`.
+    pub kind: String,
+    /// 0-based half-open span of the location. Joins are reduced to their extremes. If the
+    /// feature wraps around the origin of a circular sequence, the end goes past its length.
+    pub start: usize,
+    pub end: usize,
+    /// Qualifiers as `(name, value)`, without the quotes. The value is empty for flags.
+    pub qualifiers: Vec<(String, String)>,
+}
+
+impl Feature {
+    /// Value of the first qualifier called `name`, if any.
+    pub fn qualifier(&self, name: &str) -> Option<&str> {
+        self.qualifiers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// A human readable name: the gene, the locus tag, the label or the product, in that
+    /// order, and the kind of the feature if there is none.
+    pub fn name(&self) -> &str {
+        ["gene", "locus_tag", "label", "product"]
+            .iter()
+            .find_map(|name| self.qualifier(name))
+            .unwrap_or(&self.kind)
+    }
+
+    pub const fn overlaps(&self, range: &Range<usize>) -> bool {
+        self.start < range.end && range.start < self.end
+    }
+}
+
+/// Flat file format, detected from the first line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FlatFile {
+    GenBank,
+    Embl,
+}
+
+impl FlatFile {
+    /// Number of bytes needed by [`Self::detect`].
+    pub(crate) const DETECT_LEN: usize = 5;
+
+    pub(crate) fn detect(head: &[u8]) -> Option<Self> {
+        if head.starts_with(b"LOCUS") {
+            Some(Self::GenBank)
+        } else if head.starts_with(b"ID   ") {
+            Some(Self::Embl)
+        } else {
+            None
+        }
+    }
+}
+
+/// Where we are inside a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Header,
+    Features,
+    Sequence,
+}
+
+/// Parse the GenBank or EMBL records of `input`, calling `f` on each of them.
+///
+/// The id of a record is its (first) accession, or its name if it has none. The sequence
+/// is returned as is, without sanitizing it.
+//
+// Both formats share the layout of the feature table: the key starts at column 5 and the
+// location (or the qualifiers, on the following lines) at column 21. EMBL lines start with
+// a two letter code ("FT", "SQ"...) instead of the keywords of GenBank ("FEATURES", "ORIGIN").
+pub(crate) fn parse<R: BufRead>(
+    input: R,
+    format: FlatFile,
+    mut f: impl FnMut(SeqRecord) -> Result<()>,
+) -> Result<()> {
+    let mut record = empty_record();
+    let mut section = Section::Header;
+    let mut location = String::new();
+    // Name (LOCUS / ID) and first accession of the current record
+    let mut name: Option<String> = None;
+    let mut accession: Option<String> = None;
+
+    for (idx, line) in input.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with("//") {
+            let Some(name) = name.take() else {
+                bail!("line {}: end of record ('//') without a header", idx + 1)
+            };
+            finish_feature(&mut record, &mut location);
+            record.id = accession.take().unwrap_or(name);
+            // The length of the sequence is only known now
+            for feature in &mut record.features {
+                if feature.end <= feature.start {
+                    feature.end += record.seq.len();
+                }
+            }
+            record.features.sort_by_key(|feature| feature.start);
+            f(std::mem::replace(&mut record, empty_record()))?;
+            section = Section::Header;
+            continue;
+        }
+
+        let (tag, body) = match format {
+            FlatFile::GenBank => genbank_tag(line),
+            FlatFile::Embl => (line.get(..2).unwrap_or(line), line.get(5..).unwrap_or("")),
+        };
+        // GenBank separates the words with spaces, EMBL with semicolons
+        let mut words = body
+            .split(|c: char| c.is_whitespace() || c == ';')
+            .filter(|word| !word.is_empty());
+
+        match (format, tag) {
+            (FlatFile::GenBank, "LOCUS") | (FlatFile::Embl, "ID") => {
+                if name.is_some() {
+                    bail!(
+                        "line {}: new record before the end ('//') of the previous",
+                        idx + 1
+                    )
+                }
+                name = Some(words.next().unwrap_or_default().to_string());
+                record.circular = words.any(|word| word.eq_ignore_ascii_case("circular"));
+            }
+            // Keep the first accession of the first line
+            (FlatFile::GenBank, "ACCESSION") | (FlatFile::Embl, "AC") if accession.is_none() => {
+                accession = words.next().map(String::from);
+            }
+            (FlatFile::GenBank, "FEATURES") => section = Section::Features,
+            (FlatFile::GenBank, "ORIGIN") | (FlatFile::Embl, "SQ") => {
+                finish_feature(&mut record, &mut location);
+                section = Section::Sequence;
+            }
+            // The EMBL prefix is outside of the columns of the feature table
+            (FlatFile::Embl, "FT") => feature_line(&mut record, &mut location, line),
+            (FlatFile::GenBank, "") if section == Section::Features => {
+                feature_line(&mut record, &mut location, line);
+            }
+            (FlatFile::Embl, "  ") | (FlatFile::GenBank, "") if section == Section::Sequence => {
+                record.seq.extend(
+                    line.bytes()
+                        .filter(|byte| !byte.is_ascii_digit() && !byte.is_ascii_whitespace()),
+                );
+            }
+            (FlatFile::GenBank, _) if section == Section::Features => {
+                // Another keyword (BASE COUNT, CONTIG...) ends the features
+                finish_feature(&mut record, &mut location);
+                section = Section::Header;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(name) = name {
+        bail!("unexpected end of file inside record '{name}'")
+    }
+
+    Ok(())
+}
+
+const fn empty_record() -> SeqRecord {
+    SeqRecord {
+        id: String::new(),
+        seq: Vec::new(),
+        circular: false,
+        features: Vec::new(),
+        soft_mask: None,
+    }
+}
+
+/// Split a GenBank line into its keyword (empty for indented lines) and the rest.
+fn genbank_tag(line: &str) -> (&str, &str) {
+    if line.starts_with(' ') {
+        return ("", line);
+    }
+    // "BASE COUNT" has a space, but the body always starts at column 12
+    let tag = line.get(..12).unwrap_or(line).trim_end();
+    let body = line.get(12..).unwrap_or("").trim_start();
+    (tag, body)
+}
+
+/// Handle a line of the feature table, in the GenBank columns.
+fn feature_line(record: &mut SeqRecord, location: &mut String, line: &str) {
+    let key = line.get(5..21).unwrap_or("").trim();
+    let rest = line.get(21..).unwrap_or("").trim();
+
+    if !key.is_empty() {
+        // A new feature
+        finish_feature(record, location);
+        record.features.push(Feature {
+            kind: key.to_string(),
+            start: 0,
+            end: 0,
+            qualifiers: Vec::new(),
+        });
+        location.push_str(rest);
+        return;
+    }
+
+    let Some(feature) = record.features.last_mut() else {
+        return;
+    };
+
+    if let Some(qualifier) = rest.strip_prefix('/') {
+        let (name, value) = qualifier.split_once('=').unwrap_or((qualifier, ""));
+        feature
+            .qualifiers
+            .push((name.to_string(), value.trim_matches('"').to_string()));
+    } else if let Some((_, value)) = feature.qualifiers.last_mut() {
+        // Continuation of a qualifier value
+        value.push(' ');
+        value.push_str(rest.trim_end_matches('"'));
+    } else {
+        // Continuation of the location
+        location.push_str(rest);
+    }
+}
+
+/// Set the span of the last feature from its (complete) location.
+fn finish_feature(record: &mut SeqRecord, location: &mut String) {
+    if location.is_empty() {
+        return;
+    }
+    let span = location_span(location, record.circular);
+    location.clear();
+
+    match span {
+        Some(span) => {
+            let feature = record
+                .features
+                .last_mut()
+                .expect("a feature has a location");
+            feature.start = span.start;
+            feature.end = span.end;
+        }
+        // References to other records, we can not place them
+        None => {
+            record.features.pop();
+        }
+    }
+}
+
+/// 0-based half-open span of a location, from its smallest to its largest position.
+///
+/// For example `complement(join(<10..20,30..>40))` spans `9..40`.
+///
+/// In a `circular` sequence, a join whose parts are not increasing wraps around the origin:
+/// it spans from the start of its first part to the end of its last one, which is before
+/// the start. For example `join(9000..9500,1..200)` spans `8999..200`.
+fn location_span(location: &str, circular: bool) -> Option<Range<usize>> {
+    if location.contains(':') {
+        return None;
+    }
+
+    let parts: Vec<_> = location
+        .split(',')
+        .filter_map(|part| {
+            let positions = part
+                .split(|c: char| !c.is_ascii_digit())
+                .filter_map(|number| number.parse::<usize>().ok());
+            let (min, max) = positions.fold((usize::MAX, 0), |(min, max), pos| {
+                (min.min(pos), max.max(pos))
+            });
+            (min <= max).then_some(min..max)
+        })
+        .collect();
+    if parts.iter().any(|part| part.start == 0) {
+        return None;
+    }
+
+    let wraps = parts.windows(2).any(|pair| pair[1].start < pair[0].start);
+    if circular && wraps {
+        let (first, last) = (parts.first()?, parts.last()?);
+        return Some(first.start - 1..last.end);
+    }
+    let min = parts.iter().map(|part| part.start).min()?;
+    let max = parts.iter().map(|part| part.end).max()?;
+    Some(min - 1..max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::InvertedRepeat;
+
+    const GENBANK: &str = "\
+LOCUS       pTEST                     40 bp    DNA     circular SYN 01-JAN-2000
+DEFINITION  Test plasmid.
+ACCESSION   AB000001 AB000002
+VERSION     AB000001.1
+FEATURES             Location/Qualifiers
+     source          1..40
+                     /organism=\"synthetic
+                     construct\"
+     gene            complement(join(<3..10,20..>25))
+                     /gene=\"tst\"
+     misc_feature    OTHER01.1:1..5
+                     /note=\"remote\"
+     CDS             30..38
+                     /product=\"test protein\"
+                     /pseudo
+BASE COUNT       10 a     10 c     10 g     10 t
+ORIGIN
+        1 acgtacgtac gtacgtacgt acgtacgtac
+       31 gtacgtacgt
+//
+LOCUS       linear1                    8 bp    DNA     linear   SYN 01-JAN-2000
+ORIGIN
+        1 aaaacccc
+//
+";
+
+    const EMBL: &str = "\
+ID   X56734; SV 1; circular; mRNA; STD; PLN; 12 BP.
+XX
+AC   X56734; S46826;
+AC   X00000;
+XX
+FH   Key             Location/Qualifiers
+FH
+FT   source          1..12
+FT                   /organism=\"Trifolium repens\"
+FT   CDS             4..>9
+FT                   /gene=\"lin\"
+XX
+SQ   Sequence 12 BP; 3 A; 3 C; 3 G; 3 T; 0 other;
+     aaacccgggt tt                                                        12
+//
+";
+
+    fn parse_all(text: &str, format: FlatFile) -> Result<Vec<SeqRecord>> {
+        let mut records = Vec::new();
+        parse(text.as_bytes(), format, |record| {
+            records.push(record);
+            Ok(())
+        })?;
+        Ok(records)
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            FlatFile::detect(GENBANK.as_bytes()),
+            Some(FlatFile::GenBank)
+        );
+        assert_eq!(FlatFile::detect(EMBL.as_bytes()), Some(FlatFile::Embl));
+        assert_eq!(FlatFile::detect(b">seq0\nacgt"), None);
+    }
+
+    #[test]
+    fn test_parse_genbank() {
+        let records = parse_all(GENBANK, FlatFile::GenBank).unwrap();
+        assert_eq!(records.len(), 2);
+
+        let record = &records[0];
+        assert_eq!(record.id, "AB000001");
+        assert!(record.circular);
+        assert_eq!(record.seq, b"acgtacgtacgtacgtacgtacgtacgtacgtacgtacgt");
+
+        let features = &record.features;
+        assert_eq!(features.len(), 3);
+        assert_eq!((features[0].start, features[0].end), (0, 40));
+        assert_eq!(
+            features[0].qualifier("organism"),
+            Some("synthetic construct")
+        );
+        assert_eq!(features[0].name(), "source");
+        assert_eq!((features[1].start, features[1].end), (2, 25));
+        assert_eq!(features[1].name(), "tst");
+        assert_eq!((features[2].start, features[2].end), (29, 38));
+        assert_eq!(features[2].name(), "test protein");
+        assert_eq!(features[2].qualifier("pseudo"), Some(""));
+
+        // No accession, so the name is used instead
+        assert_eq!(records[1].id, "linear1");
+        assert!(!records[1].circular);
+        assert_eq!(records[1].seq, b"aaaacccc");
+        assert!(records[1].features.is_empty());
+    }
+
+    #[test]
+    fn test_parse_wrapping_feature() {
+        let genbank = "\
+LOCUS       pWRAP                     12 bp    DNA     circular SYN 01-JAN-2000
+FEATURES             Location/Qualifiers
+     gene            join(10..12,1..2)
+                     /gene=\"ori\"
+     gene            5..7
+ORIGIN
+        1 acgtacgtac gt
+//
+";
+        let records = parse_all(genbank, FlatFile::GenBank).unwrap();
+        let record = &records[0];
+        // Sorted by start: the end of the wrapping gene goes past the length
+        let spans: Vec<_> = record.features.iter().map(|f| (f.start, f.end)).collect();
+        assert_eq!(spans, [(4, 7), (9, 14)]);
+
+        let names = |ir| {
+            record
+                .overlapping_features(&ir)
+                .map(Feature::name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(InvertedRepeat::new(0, 3, 0)), ["ori"]);
+        assert_eq!(names(InvertedRepeat::new(10, 13, 0)), ["ori"]);
+        assert_eq!(names(InvertedRepeat::new(3, 8, 2)), ["gene"]);
+        assert!(names(InvertedRepeat::new(2, 3, 0)).is_empty());
+    }
+
+    #[test]
+    fn test_parse_embl() {
+        let records = parse_all(EMBL, FlatFile::Embl).unwrap();
+        assert_eq!(records.len(), 1);
+
+        let record = &records[0];
+        assert_eq!(record.id, "X56734");
+        assert!(record.circular);
+        assert_eq!(record.seq, b"aaacccgggttt");
+        assert_eq!(record.features.len(), 2);
+        assert_eq!(record.features[1].kind, "CDS");
+        assert_eq!((record.features[1].start, record.features[1].end), (3, 9));
+        assert_eq!(record.features[1].name(), "lin");
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let truncated = &GENBANK[..GENBANK.find("//").unwrap()];
+        assert!(parse_all(truncated, FlatFile::GenBank).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn test_location_span() {
+        assert_eq!(location_span("1..40", false), Some(0..40));
+        assert_eq!(
+            location_span("complement(join(<10..20,30..>40))", false),
+            Some(9..40)
+        );
+        assert_eq!(location_span("7", false), Some(6..7));
+        assert_eq!(location_span("12^13", false), Some(11..13));
+        assert_eq!(location_span("OTHER01.1:1..5", false), None);
+        assert_eq!(location_span("0..5", false), None);
+
+        // Across the origin of a circular sequence
+        let location = "join(9000..9500,1..200)";
+        assert_eq!(location_span(location, true), Some(8999..200));
+        assert_eq!(location_span(location, false), Some(0..9500));
+        assert_eq!(
+            location_span("complement(join(9000..9500,1..100,150..200))", true),
+            Some(8999..200)
+        );
+        assert_eq!(location_span("join(1..100,150..200)", true), Some(0..200));
+    }
+}
@@ -1,17 +1,101 @@
 // This may present differences in the ordering with IUPACpal - but it is simpler to write
-#![allow(clippy::needless_range_loop)]
 #![allow(clippy::similar_names)]
 
-use crate::{config::Config, matrix::MatchMatrix};
-use std::fmt::Write;
+use crate::{
+    config::Config, config::SearchParams, constants::OutputFormat, flatfile::Feature,
+    input::overlapping_features, ir::ArmAlignment, ir::InvertedRepeat, ir::RepeatKind,
+    mask::SoftMask, matrix::MatchMatrix, matrix::MatchMode, matrix::NPolicy, report::ReportMode,
+    score::ScoreWeights,
+};
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::Range;
 
-const fn int_size(x: usize) -> usize {
-    (x.ilog10() + 1) as usize
+/// The sequence whose IRs are written.
+pub struct Sequence<'a> {
+    /// A circular sequence is followed by its start, to slice the IRs wrapping around it.
+    bytes: Cow<'a, [u8]>,
+    /// Length of the sequence if it is circular: the positions are written modulo it.
+    circular_len: Option<usize>,
+    /// Annotations of the sequence, sorted by start.
+    features: &'a [Feature],
+    /// RNA mode: the sequence is written with `u`, and the wobble pairs are counted.
+    rna: bool,
+    /// The soft-masked positions of the sequence, if any.
+    soft_mask: Option<&'a SoftMask>,
+    /// Whether the IRs were found allowing indels, and have an alignment to report.
+    edits: bool,
+    /// How the arms of the repeats pair.
+    kind: RepeatKind,
+}
+
+impl<'a> Sequence<'a> {
+    pub fn new(seq: &'a [u8], params: &SearchParams) -> Self {
+        let mut bytes = Cow::Borrowed(seq);
+        let mut circular_len = None;
+        if params.circular {
+            // The IRs wrapping around the origin end at most one span after it
+            let span = params
+                .max_len
+                .saturating_mul(2)
+                .saturating_add(params.max_gap);
+            bytes = Cow::Owned([seq, &seq[..span.min(seq.len())]].concat());
+            circular_len = Some(seq.len());
+        }
+        if params.rna && bytes.contains(&b't') {
+            for byte in bytes.to_mut().iter_mut().filter(|byte| **byte == b't') {
+                *byte = b'u';
+            }
+        }
+        Self {
+            bytes,
+            circular_len,
+            features: &[],
+            rna: params.rna,
+            soft_mask: None,
+            edits: params.max_edits.is_some(),
+            kind: params.kind,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_features(mut self, features: &'a [Feature]) -> Self {
+        self.features = features;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_soft_mask(mut self, soft_mask: Option<&'a SoftMask>) -> Self {
+        self.soft_mask = soft_mask;
+        self
+    }
+
+    /// Score of `ir` with `weights`, also for an IR wrapping around the origin.
+    pub fn score(&self, ir: &InvertedRepeat, weights: &ScoreWeights) -> f64 {
+        weights.score(ir, self.as_bytes())
+    }
+
+    /// Fraction of the arms of `ir` that is soft-masked. Zero without a soft mask.
+    pub fn masked_fraction(&self, ir: &InvertedRepeat) -> f64 {
+        self.soft_mask
+            .map_or(0.0, |soft_mask| soft_mask.masked_fraction(ir))
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// 1-based position of the 0-based `pos`, wrapped around a circular sequence.
+    fn pos1(&self, pos: usize) -> usize {
+        self.circular_len.map_or(pos, |n| pos % n) + 1
+    }
 }
 
 /// Follows [IUPACpal](https://github.com/steven31415/IUPACpal) convention
 /// of calling Inverted Repeats, palindromes
 pub fn fmt_classic_header(config: &Config, n: usize) -> String {
+    let (start, end) = config.scanned_range(n);
     format!(
         "Palindromes of: {}\n\
         Sequence name: {}\n\
@@ -26,8 +110,8 @@ pub fn fmt_classic_header(config: &Config, n: usize) -> String {
         &config.input_file,
         &config.seq_name,
         n,
-        1,
-        n,
+        start,
+        end,
         config.params.min_len,
         config.params.max_len,
         config.params.max_gap,
@@ -35,145 +119,768 @@ pub fn fmt_classic_header(config: &Config, n: usize) -> String {
     )
 }
 
-pub fn fmt_classic(
-    irs: &[(usize, usize, usize)],
-    seq: &[u8],
+/// Name of the column of the right arm, as written: reverse complemented, reversed, or as is.
+const fn right_arm_column(kind: RepeatKind) -> &'static str {
+    match kind {
+        RepeatKind::Inverted => "reverse_complement",
+        RepeatKind::Mirror => "reverse",
+        RepeatKind::Direct => "repeat",
+    }
+}
+
+pub fn fmt_csv_header(config: &Config) -> String {
+    let mut header = format!(
+        "start_n,end_n,nucleotide,start_ir,end_ir,{},matching",
+        right_arm_column(config.params.kind)
+    );
+    if config.params.max_edits.is_some() {
+        header.push_str(",cigar");
+    }
+    if config.soft_mask {
+        header.push_str(",masked_fraction");
+    }
+    if config.score {
+        header.push_str(",score");
+    }
+    header
+}
+
+pub fn fmt_custom_header(config: &Config) -> String {
+    format!(
+        "ir_start,motif,gap_motif,{}",
+        right_arm_column(config.params.kind)
+    )
+}
+
+pub fn fmt_gff3_header(config: &Config, n: usize) -> String {
+    format!("##gff-version 3\n{}", fmt_gff3_sequence_region(config, n))
+}
+
+fn fmt_gff3_sequence_region(config: &Config, n: usize) -> String {
+    format!(
+        "##sequence-region {} 1 {n}",
+        gff3_escape(config.seq_name, GFF3_SEQID_SAFE)
+    )
+}
+
+/// Opens the JSON document: run configuration, sequence metadata and the array of IRs,
+/// that is closed by [`fmt_footer`]. As in the [`Config`] display, the options with their
+/// default value are left out of the configuration, except for the thresholds.
+///
+/// The configuration is enough to reproduce the run: the regions are written (1-based and
+/// inclusive) instead of their source, and a custom match matrix as the list of its pairs.
+pub fn fmt_json_header(config: &Config, n: usize) -> String {
+    let params = &config.params;
+    let mut optional = String::new();
+    if let Some(max_edits) = params.max_edits {
+        let _ = write!(optional, ",\"max_edits\":{max_edits}");
+    }
+    if params.kind != RepeatKind::Inverted {
+        let _ = write!(optional, ",\"kind\":\"{}\"", params.kind);
+    }
+    if params.report != ReportMode::All {
+        let _ = write!(optional, ",\"report\":\"{}\"", params.report);
+    }
+    if params.circular {
+        optional.push_str(",\"circular\":true");
+    }
+    if params.rna {
+        optional.push_str(",\"rna\":true");
+    }
+    if params.match_mode != MatchMode::Permissive {
+        let _ = write!(optional, ",\"match_mode\":\"{}\"", params.match_mode);
+    }
+    if let MatchMode::Custom(matrix) = &params.match_mode {
+        let _ = write!(optional, ",\"match_pairs\":[{}]", json_match_pairs(matrix));
+    }
+    if params.n_policy != NPolicy::MatchAll {
+        let _ = write!(optional, ",\"n_policy\":\"{}\"", params.n_policy);
+    }
+    if let Some(regions) = &config.regions {
+        let regions: Vec<_> = regions
+            .iter()
+            .map(|region| format!("[{},{}]", region.start + 1, region.end))
+            .collect();
+        let _ = write!(optional, ",\"regions\":[{}]", regions.join(","));
+    }
+    if let Some(window_size) = config.window_size {
+        let _ = write!(optional, ",\"window_size\":{window_size}");
+    }
+    if config.soft_mask {
+        optional.push_str(",\"soft_mask\":true");
+    }
+    if let Some(max_masked_fraction) = config.max_masked_fraction {
+        let _ = write!(
+            optional,
+            ",\"max_masked_fraction\":{}",
+            json_f64(max_masked_fraction)
+        );
+    }
+    if config.score || config.min_score.is_some() || config.sort_by_score {
+        let weights = &config.score_weights;
+        let _ = write!(
+            optional,
+            ",\"score_weights\":{{\"match\":{},\"mismatch\":{},\"indel\":{},\"ambiguous\":{},\"gap\":{}}}",
+            json_f64(weights.matches),
+            json_f64(weights.mismatches),
+            json_f64(weights.indels),
+            json_f64(weights.ambiguous),
+            json_f64(weights.gap),
+        );
+    }
+    if let Some(min_score) = config.min_score {
+        let _ = write!(optional, ",\"min_score\":{}", json_f64(min_score));
+    }
+    if config.sort_by_score {
+        optional.push_str(",\"sort_by\":\"score\"");
+    }
+    format!(
+        "{{\"config\":{{\"input_file\":{},\"seq_name\":{},\
+        \"min_len\":{},\"max_len\":{},\"max_gap\":{},\"mismatches\":{}{optional}}},\
+        \"sequence\":{{\"name\":{},\"length\":{}}},\"irs\":[",
+        json_str(config.input_file),
+        json_str(config.seq_name),
+        params.min_len,
+        params.max_len,
+        params.max_gap,
+        params.mismatches,
+        json_str(config.seq_name),
+        n,
+    )
+}
+
+/// The pairs of IUPAC codes of `matrix` (with the right arm complemented, see
+/// [`MatchMatrix::pairs`]), as JSON strings of two codes.
+fn json_match_pairs(matrix: &MatchMatrix) -> String {
+    // u pairs as t
+    const CODES: &[u8] = b"acgtryswkmbdhvn";
+    let mut pairs = Vec::new();
+    for (idx, &a) in CODES.iter().enumerate() {
+        for &b in &CODES[idx..] {
+            if matrix.pairs(a, b) {
+                pairs.push(format!("\"{}{}\"", a as char, b as char));
+            }
+        }
+    }
+    pairs.join(",")
+}
+
+/// A JSON number, or `null` if it is not finite.
+fn json_f64(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        String::from("null")
+    }
+}
+
+/// Header of the given output format. It is empty for the line based formats.
+pub fn fmt_header(config: &Config, n: usize) -> String {
+    match config.output_format {
+        OutputFormat::Classic => fmt_classic_header(config, n),
+        OutputFormat::Csv => fmt_csv_header(config),
+        OutputFormat::Custom => fmt_custom_header(config),
+        OutputFormat::Json => fmt_json_header(config, n),
+        OutputFormat::Gff3 => fmt_gff3_header(config, n),
+        OutputFormat::Jsonl | OutputFormat::Bed | OutputFormat::Bed12 => String::new(),
+    }
+}
+
+/// Header of a sequence written after others to the same output. It is the same as
+/// [`fmt_header`], except for gff3 where the version directive is only at the top.
+pub fn fmt_following_header(config: &Config, n: usize) -> String {
+    match config.output_format {
+        OutputFormat::Gff3 => fmt_gff3_sequence_region(config, n),
+        _ => fmt_header(config, n),
+    }
+}
+
+/// Formats made of whole lines per IR (after the header, if any), with no empty line
+/// at the end.
+pub const fn is_line_based(format: &OutputFormat) -> bool {
+    matches!(
+        format,
+        OutputFormat::Jsonl | OutputFormat::Bed | OutputFormat::Bed12 | OutputFormat::Gff3
+    )
+}
+
+/// Written before an IR, after `written` others. Only json needs to separate them.
+pub const fn fmt_separator(format: &OutputFormat, written: usize) -> &'static str {
+    match format {
+        OutputFormat::Json if written > 0 => ",\n",
+        _ => "",
+    }
+}
+
+/// Written after the last of the `written` IRs. Only json needs to close the document.
+pub const fn fmt_footer(format: &OutputFormat, written: usize) -> &'static str {
+    match format {
+        OutputFormat::Json if written == 0 => "]}",
+        OutputFormat::Json => "\n]}",
+        _ => "",
+    }
+}
+
+/// Write a single IR in the output format of the config.
+///
+/// `nth` numbers the IR in the bed and gff3 names, from 0. The IRs must be separated with
+/// [`fmt_separator`]. Every IR is one line for json and jsonl, where the names of the
+/// features of the sequence overlapping the IR are added (if it has any).
+///
+/// For a circular sequence, the positions of the IRs wrapping around the origin are
+/// written modulo its length, except for gff3 where the end goes past the length of the
+/// sequence (its convention for circular sequences). Bed splits them into two features,
+/// one at each side of the origin.
+///
+/// If `config.soft_mask` is set, csv, json and jsonl also report the soft-masked fraction
+/// of the arms. The `score` of the IR (see [`Sequence::score`]) is reported if given.
+#[allow(clippy::too_many_arguments)]
+pub fn write_ir<W: Write>(
+    out: &mut W,
+    config: &Config,
+    ir: &InvertedRepeat,
+    nth: usize,
+    seq: &Sequence,
+    score: Option<f64>,
     matrix: &MatchMatrix,
     complement: &[u8; 128],
-) -> String {
-    let mut out = String::new();
+) -> io::Result<()> {
+    let masked = config.soft_mask.then(|| seq.masked_fraction(ir));
+    match config.output_format {
+        OutputFormat::Classic => write_classic(out, ir, seq, matrix, complement),
+        OutputFormat::Csv => write_csv(out, ir, seq, masked, score, matrix, complement),
+        OutputFormat::Custom => write_custom(out, ir, seq),
+        OutputFormat::Json => write_json(out, None, ir, seq, masked, score, matrix, complement),
+        OutputFormat::Jsonl => {
+            let seq_name = Some(config.seq_name);
+            write_json(out, seq_name, ir, seq, masked, score, matrix, complement)?;
+            out.write_all(b"\n")
+        }
+        OutputFormat::Bed => write_bed(out, config, ir, nth, seq, score),
+        OutputFormat::Bed12 => write_bed12(out, config, ir, nth, seq, score),
+        OutputFormat::Gff3 => write_gff3(out, config, ir, nth, seq, matrix, complement),
+    }
+}
 
+pub fn write_classic<W: Write>(
+    out: &mut W,
+    ir: &InvertedRepeat,
+    seq: &Sequence,
+    matrix: &MatchMatrix,
+    complement: &[u8; 128],
+) -> io::Result<()> {
     let pad = "         ";
     let pad_length = pad.len(); // 9
 
-    for &(left, right, gap) in irs {
-        let outer_left = left + 1;
-        let outer_right = right + 1;
-        let inner_left = (outer_left + outer_right - 1 - gap) / 2;
-        let inner_right = (outer_right + outer_left + 1 + gap) / 2;
-
-        let ol_pad = " ".repeat(pad_length - int_size(outer_left));
-        let il_pad = " ".repeat(pad_length - int_size(inner_left));
-        let or_pad = " ".repeat(pad_length - int_size(outer_right));
-        let ir_pad = " ".repeat(pad_length - int_size(inner_right));
-
-        // 1. First line (nucleotide strand)
-        write!(&mut out, "{outer_left}{ol_pad}").unwrap();
-        for i in left..inner_left {
-            out.push(seq[i] as char);
-        }
-        writeln!(&mut out, "{il_pad}{inner_left}").unwrap();
-
-        // 2. Second line (matching bars)
-        out.push_str(pad);
-        for i in 0..=(inner_left - outer_left) {
-            let l = seq[left + i];
-            let r = seq[right - i];
-            let matching = matrix.match_u8(l, complement[r as usize]);
-            out.push(if matching { '|' } else { ' ' });
-        }
-        out.push('\n');
+    let outer_left = seq.pos1(ir.left);
+    let inner_left = seq.pos1(ir.left_arm().end - 1);
+    let (first_right, last_right) = right_arm_ends(ir, seq);
+    // With indels, the unpaired nucleotides face a '-'
+    let (left_row, right_row): (Vec<u8>, Vec<u8>) = arm_columns(ir, seq)
+        .map(|(l, r)| (l.unwrap_or(b'-'), r.unwrap_or(b'-')))
+        .unzip();
 
-        // 3. Third line (reverse complement strand)
-        write!(&mut out, "{outer_right}{or_pad}").unwrap();
-        for i in (inner_right..=outer_right).rev() {
-            out.push(seq[i - 1] as char);
-        }
-        write!(&mut out, "{ir_pad}{inner_right}\n\n").unwrap();
+    // 1. First line (nucleotide strand)
+    write!(out, "{outer_left:<pad_length$}")?;
+    out.write_all(&left_row)?;
+    writeln!(out, "{inner_left:>pad_length$}")?;
+
+    // 2. Second line (matching bars)
+    out.write_all(pad.as_bytes())?;
+    write_matching(out, ir, seq, matrix, complement, (b'|', b':', b' ', b' '))?;
+    out.write_all(b"\n")?;
+
+    // 3. Third line (reverse complement strand)
+    write!(out, "{first_right:<pad_length$}")?;
+    out.write_all(&right_row)?;
+    write!(out, "{last_right:>pad_length$}\n\n")
+}
+
+pub fn write_csv<W: Write>(
+    out: &mut W,
+    ir: &InvertedRepeat,
+    seq: &Sequence,
+    masked: Option<f64>,
+    score: Option<f64>,
+    matrix: &MatchMatrix,
+    complement: &[u8; 128],
+) -> io::Result<()> {
+    let outer_left = seq.pos1(ir.left);
+    let inner_left = seq.pos1(ir.left_arm().end - 1);
+    let (first_right, last_right) = right_arm_ends(ir, seq);
+
+    write!(out, "{outer_left},{inner_left},")?;
+
+    // 1. Nucleotide strand
+    out.write_all(ir.left_arm_seq(seq.as_bytes()))?;
+    out.write_all(b",")?;
+
+    write!(out, "{first_right},{last_right},")?;
+
+    // 2. Reverse complement
+    write_right_arm(out, ir, seq)?;
+    out.write_all(b",")?;
+
+    // 3. Matching line
+    write_matching(out, ir, seq, matrix, complement, MATCHING_SYMBOLS)?;
+    if seq.edits {
+        write!(out, ",{}", cigar(ir))?;
+    }
+    if let Some(masked) = masked {
+        write!(out, ",{masked:.3}")?;
     }
+    if let Some(score) = score {
+        write!(out, ",{score:.2}")?;
+    }
+    out.write_all(b"\n")
+}
 
-    out
+pub fn write_custom<W: Write>(out: &mut W, ir: &InvertedRepeat, seq: &Sequence) -> io::Result<()> {
+    let outer_left = seq.pos1(ir.left);
+
+    write!(out, "{outer_left},")?;
+
+    // 1. Nucleotide strand
+    out.write_all(ir.left_arm_seq(seq.as_bytes()))?;
+    out.write_all(b",")?;
+
+    // 2. Gap motif
+    out.write_all(ir.gap_seq(seq.as_bytes()))?;
+    out.write_all(b",")?;
+
+    // 3. Reverse complement
+    write_right_arm(out, ir, seq)?;
+    out.write_all(b"\n")
+}
+
+/// BED6 line: chrom, start, end, name, score and strand. Coordinates are 0-based, half-open.
+///
+/// The score is the one of the IR if it is reported (rounded and clamped to `0..=1000`),
+/// and [`bed_score`] otherwise.
+///
+/// An IR wrapping around the origin of a circular sequence is split into two lines with
+/// the same name, one at each side of the origin.
+pub fn write_bed<W: Write>(
+    out: &mut W,
+    config: &Config,
+    ir: &InvertedRepeat,
+    nth: usize,
+    seq: &Sequence,
+    score: Option<f64>,
+) -> io::Result<()> {
+    for part in bed_parts(ir, seq) {
+        write_bed6(out, config, ir, nth, seq, score, part)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// BED12 line: the arms are the two blocks, and the loop is the gap between them.
+///
+/// As in [`write_bed`], an IR wrapping around the origin is split into two lines. Each one
+/// only spans its blocks: the parts of the arms at its side of the origin.
+pub fn write_bed12<W: Write>(
+    out: &mut W,
+    config: &Config,
+    ir: &InvertedRepeat,
+    nth: usize,
+    seq: &Sequence,
+    score: Option<f64>,
+) -> io::Result<()> {
+    for part in bed_parts(ir, seq) {
+        let blocks: Vec<_> = [ir.left_arm(), ir.right_arm()]
+            .into_iter()
+            .map(|arm| arm.start.max(part.start)..arm.end.min(part.end))
+            .filter(|block| !block.is_empty())
+            .collect();
+        let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else {
+            continue;
+        };
+        let part = first.start..last.end;
+        write_bed6(out, config, ir, nth, seq, score, part.clone())?;
+        let thick_start = part.start % seq.circular_len.unwrap_or(usize::MAX);
+        let sizes: Vec<_> = blocks.iter().map(|block| block.len().to_string()).collect();
+        let starts: Vec<_> = blocks
+            .iter()
+            .map(|block| (block.start - first.start).to_string())
+            .collect();
+        writeln!(
+            out,
+            "\t{}\t{}\t0\t{}\t{}\t{}",
+            thick_start,
+            thick_start + part.len(),
+            blocks.len(),
+            sizes.join(","),
+            starts.join(","),
+        )?;
+    }
+    Ok(())
 }
 
-pub fn fmt_csv_header() -> String {
-    String::from("start_n,end_n,nucleotide,start_ir,end_ir,reverse_complement,matching")
+/// The parts of `ir` to write as BED features: the IR itself, or, if it wraps around the
+/// origin of a circular sequence, its two sides of the origin. They are not taken modulo
+/// the length of the sequence.
+fn bed_parts(ir: &InvertedRepeat, seq: &Sequence) -> Vec<Range<usize>> {
+    let span = ir.left..ir.right + 1;
+    match seq.circular_len {
+        Some(n) if span.start < n && n < span.end => vec![span.start..n, n..span.end],
+        _ => vec![span],
+    }
 }
 
-pub fn fmt_csv(
-    irs: &[(usize, usize, usize)],
-    seq: &[u8],
+/// BED6 columns of the part `part` of `ir` (see [`bed_parts`]), without the line break.
+fn write_bed6<W: Write>(
+    out: &mut W,
+    config: &Config,
+    ir: &InvertedRepeat,
+    nth: usize,
+    seq: &Sequence,
+    score: Option<f64>,
+    part: Range<usize>,
+) -> io::Result<()> {
+    let score = score.map_or_else(
+        || bed_score(ir, config.params.max_len),
+        // The browsers only accept integers in 0..=1000
+        |score| score.round().clamp(0.0, 1000.0) as usize,
+    );
+    let n = seq.circular_len.unwrap_or(usize::MAX);
+    let start = part.start % n;
+    write!(
+        out,
+        "{}\t{}\t{}\tir{}\t{}\t.",
+        config.seq_name,
+        start,
+        start + part.len(),
+        nth + 1,
+        score,
+    )
+}
+
+/// Score in `0..=1000` for the browsers to shade the features: the matching nucleotides
+/// of an arm relative to `max_len`.
+fn bed_score(ir: &InvertedRepeat, max_len: usize) -> usize {
+    let matching = ir.arm_len().saturating_sub(ir.edits());
+    (1000 * matching / max_len.max(1)).min(1000)
+}
+
+/// GFF3 lines of an IR: the parent `inverted_repeat` feature (`direct_repeat` or
+/// `repeat_region` for the other kinds), followed by its children, the two `repeat_arm`
+/// and the `loop` (if the gap is not empty).
+///
+/// Coordinates are 1-based and inclusive.
+pub fn write_gff3<W: Write>(
+    out: &mut W,
+    config: &Config,
+    ir: &InvertedRepeat,
+    nth: usize,
+    seq: &Sequence,
     matrix: &MatchMatrix,
     complement: &[u8; 128],
-) -> String {
-    let mut out = String::new();
+) -> io::Result<()> {
+    let seqid = gff3_escape(config.seq_name, GFF3_SEQID_SAFE);
+    let id = format!("ir{}", nth + 1);
+    let feature = |out: &mut W, kind: &str, start: usize, end: usize| {
+        write!(out, "{seqid}\tiirs\t{kind}\t{start}\t{end}\t.\t.\t.\t")
+    };
 
-    for &(left, right, gap) in irs {
-        let outer_left = left + 1;
-        let outer_right = right + 1;
-        let inner_left = (outer_left + outer_right - 1 - gap) / 2;
-        let inner_right = (outer_right + outer_left + 1 + gap) / 2;
+    let parent = match config.params.kind {
+        RepeatKind::Inverted => "inverted_repeat",
+        RepeatKind::Direct => "direct_repeat",
+        // There is no Sequence Ontology term for mirror repeats
+        RepeatKind::Mirror => "repeat_region",
+    };
+    feature(out, parent, ir.left + 1, ir.right + 1)?;
+    write!(
+        out,
+        "ID={id};gap={};mismatches={};",
+        ir.gap,
+        ir.mismatches()
+    )?;
+    if config.params.rna {
+        write!(
+            out,
+            "wobbles={};",
+            count_wobbles(ir, seq, matrix, complement)
+        )?;
+    }
+    if config.params.max_edits.is_some() {
+        write!(out, "edits={};cigar={};", ir.edits(), cigar(ir))?;
+    }
+    out.write_all(b"matching=")?;
+    write_matching(out, ir, seq, matrix, complement, MATCHING_SYMBOLS)?;
+    out.write_all(b"\n")?;
 
-        write!(&mut out, "{outer_left},{inner_left},").unwrap();
+    let left_arm = ir.left_arm();
+    feature(out, "repeat_arm", left_arm.start + 1, left_arm.end)?;
+    writeln!(out, "ID={id}.arm1;Parent={id}")?;
 
-        // 1. Nucleotide strand
-        for i in left..inner_left {
-            out.push(seq[i] as char);
-        }
-        out.push(',');
+    if ir.gap > 0 {
+        let gap = ir.gap_range();
+        feature(out, "loop", gap.start + 1, gap.end)?;
+        writeln!(out, "ID={id}.loop;Parent={id}")?;
+    }
 
-        write!(&mut out, "{outer_right},{inner_right},").unwrap();
+    let right_arm = ir.right_arm();
+    feature(out, "repeat_arm", right_arm.start + 1, right_arm.end)?;
+    writeln!(out, "ID={id}.arm2;Parent={id}")
+}
 
-        // 2. Reverse complement
-        for i in (inner_right..=outer_right).rev() {
-            out.push(seq[i - 1] as char);
+/// Characters that do not need escaping in a GFF3 seqid (column 1).
+const GFF3_SEQID_SAFE: &str = ".:^*$@!+_?-|";
+
+/// Percent-encode the characters of `s` that are not alphanumeric nor in `safe`.
+fn gff3_escape(s: &str, safe: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() || safe.contains(c) {
+            out.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                write!(out, "%{byte:02X}").unwrap();
+            }
         }
-        out.push(',');
-
-        // 3. Matching line
-        for i in 0..=(inner_left - outer_left) {
-            let l = seq[left + i];
-            let r = seq[right - i];
-            let matching = matrix.match_u8(l, complement[r as usize]);
-            out.push(if matching { '1' } else { '0' });
+    }
+    out
+}
+
+/// Write an IR as a JSON object. Positions are 1-based and inclusive, as in the csv format,
+/// and the arms are written in the direction of the sequence.
+///
+/// Every line of jsonl is standalone, so it also carries the sequence name.
+#[allow(clippy::too_many_arguments)]
+fn write_json<W: Write>(
+    out: &mut W,
+    seq_name: Option<&str>,
+    ir: &InvertedRepeat,
+    seq: &Sequence,
+    masked: Option<f64>,
+    score: Option<f64>,
+    matrix: &MatchMatrix,
+    complement: &[u8; 128],
+) -> io::Result<()> {
+    out.write_all(b"{")?;
+    if let Some(seq_name) = seq_name {
+        write!(out, "\"seq_name\":{},", json_str(seq_name))?;
+    }
+    write!(
+        out,
+        "\"start\":{},\"end\":{},\"arm_length\":{},\"gap\":{},\"mismatches\":{},",
+        seq.pos1(ir.left),
+        seq.pos1(ir.right),
+        ir.arm_len(),
+        ir.gap,
+        ir.mismatches(),
+    )?;
+
+    let left_arm = ir.left_arm();
+    let right_arm = ir.right_arm();
+    write!(
+        out,
+        "\"left_arm\":{{\"start\":{},\"end\":{},\"seq\":\"",
+        seq.pos1(left_arm.start),
+        seq.pos1(left_arm.end - 1)
+    )?;
+    out.write_all(ir.left_arm_seq(seq.as_bytes()))?;
+    out.write_all(b"\"},\"loop\":\"")?;
+    out.write_all(ir.gap_seq(seq.as_bytes()))?;
+    write!(
+        out,
+        "\",\"right_arm\":{{\"start\":{},\"end\":{},\"seq\":\"",
+        seq.pos1(right_arm.start),
+        seq.pos1(right_arm.end - 1)
+    )?;
+    out.write_all(ir.right_arm_seq(seq.as_bytes()))?;
+    out.write_all(b"\"},\"matching\":\"")?;
+    write_matching(out, ir, seq, matrix, complement, MATCHING_SYMBOLS)?;
+    out.write_all(b"\",\"mismatch_positions\":[")?;
+    for (idx, &pos) in ir.mismatch_positions.iter().enumerate() {
+        let sep = if idx == 0 { "" } else { "," };
+        write!(out, "{sep}{}", seq.pos1(pos))?;
+    }
+    out.write_all(b"]")?;
+    if seq.rna {
+        let wobbles = count_wobbles(ir, seq, matrix, complement);
+        write!(out, ",\"wobbles\":{wobbles}")?;
+    }
+    if seq.edits {
+        write!(out, ",\"edits\":{},\"cigar\":\"{}\"", ir.edits(), cigar(ir))?;
+    }
+    if let Some(masked) = masked {
+        write!(out, ",\"masked_fraction\":{masked:.3}")?;
+    }
+    if let Some(score) = score {
+        write!(out, ",\"score\":{score:.2}")?;
+    }
+    // Only for annotated sequences, to keep the other documents unchanged
+    if !seq.features.is_empty() {
+        out.write_all(b",\"features\":[")?;
+        let features = overlapping_features(seq.features, ir, seq.circular_len);
+        for (idx, feature) in features.enumerate() {
+            let sep = if idx == 0 { "" } else { "," };
+            write!(out, "{sep}{}", json_str(feature.name()))?;
         }
-        out.push('\n');
+        out.write_all(b"]")?;
     }
+    out.write_all(b"}")
+}
 
+/// Quote and escape `s` as a JSON string. Sequence names come from FASTA headers and can
+/// contain anything.
+fn json_str(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => write!(out, "\\u{:04x}", u32::from(c)).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
     out
 }
 
-pub fn fmt_custom_header() -> String {
-    String::from("ir_start,motif,gap_motif,reverse_complement")
+/// Write the right arm in the direction it pairs with the left arm: reversed, unless it is
+/// a direct repeat.
+fn write_right_arm<W: Write>(out: &mut W, ir: &InvertedRepeat, seq: &Sequence) -> io::Result<()> {
+    let arm = ir.right_arm_seq(seq.as_bytes());
+    if seq.kind.is_reversed() {
+        let rev: Vec<u8> = arm.iter().rev().copied().collect();
+        out.write_all(&rev)
+    } else {
+        out.write_all(arm)
+    }
 }
 
-pub fn fmt_custom(irs: &[(usize, usize, usize)], seq: &[u8]) -> String {
-    let mut out = String::new();
-
-    for &(left, right, gap) in irs {
-        let outer_left = left + 1;
-        let outer_right = right + 1;
-        let inner_left = (outer_left + outer_right - 1 - gap) / 2;
-        let inner_right = (outer_right + outer_left + 1 + gap) / 2;
+/// 1-based positions of the first and the last nucleotide of the right arm, as written by
+/// [`write_right_arm`].
+fn right_arm_ends(ir: &InvertedRepeat, seq: &Sequence) -> (usize, usize) {
+    let (outer, inner) = (seq.pos1(ir.right), seq.pos1(ir.right_arm().start));
+    if seq.kind.is_reversed() {
+        (outer, inner)
+    } else {
+        (inner, outer)
+    }
+}
 
-        write!(&mut out, "{outer_left},").unwrap();
+/// Symbols of the matching line of every format but classic: paired, wobble pair,
+/// mismatch and unpaired nucleotide.
+const MATCHING_SYMBOLS: (u8, u8, u8, u8) = (b'1', b'w', b'0', b'-');
 
-        // 1. Nucleotide strand
-        for i in left..inner_left {
-            out.push(seq[i] as char);
-        }
-        out.push(',');
+/// The nucleotides of the arms, paired from the outer pair to the inner one (from the
+/// start of the arms for direct repeats). With indels, a nucleotide can be unpaired
+/// (`None` on the other side).
+fn arm_columns<'a>(
+    ir: &'a InvertedRepeat,
+    seq: &'a Sequence,
+) -> Box<dyn Iterator<Item = (Option<u8>, Option<u8>)> + 'a> {
+    let kind = seq.kind;
+    let seq = seq.as_bytes();
+    match &ir.alignment {
+        Some(alignment) => Box::new(
+            alignment
+                .columns(ir.left, ir.right)
+                .map(|(l, r)| (l.map(|l| seq[l]), r.map(|r| seq[r]))),
+        ),
+        None if !kind.is_reversed() => Box::new(
+            ir.left_arm_seq(seq)
+                .iter()
+                .zip(ir.right_arm_seq(seq))
+                .map(|(&l, &r)| (Some(l), Some(r))),
+        ),
+        None => Box::new(
+            ir.left_arm_seq(seq)
+                .iter()
+                .zip(ir.right_arm_seq(seq).iter().rev())
+                .map(|(&l, &r)| (Some(l), Some(r))),
+        ),
+    }
+}
 
-        // 2. Gap motif
-        for i in inner_left..(inner_right - 1) {
-            out.push(seq[i] as char);
-        }
-        out.push(',');
+/// The alignment of the arms as a CIGAR string. Without indels, every pair is `M`.
+fn cigar(ir: &InvertedRepeat) -> String {
+    ir.alignment
+        .as_ref()
+        .map_or_else(|| format!("{}M", ir.arm_len()), ArmAlignment::cigar)
+}
 
-        // 3. Reverse complement
-        for i in (inner_right..=outer_right).rev() {
-            out.push(seq[i - 1] as char);
-        }
-        out.push('\n');
+/// The symbol that the matrix compares with the left arm for a nucleotide `r` of the right
+/// arm: its complement for inverted repeats, `r` itself for the other kinds.
+fn paired_symbol(r: u8, kind: RepeatKind, complement: &[u8; 128]) -> u8 {
+    if kind.is_complemented() {
+        complement[r as usize]
+    } else {
+        r
     }
+}
 
-    out
+/// Write one symbol per nucleotide pair of the arms, starting from the outer pair.
+fn write_matching<W: Write>(
+    out: &mut W,
+    ir: &InvertedRepeat,
+    seq: &Sequence,
+    matrix: &MatchMatrix,
+    complement: &[u8; 128],
+    (yes, wobble, no, unpaired): (u8, u8, u8, u8),
+) -> io::Result<()> {
+    let pair = |r: u8| paired_symbol(r, seq.kind, complement);
+    let matching: Vec<u8> = arm_columns(ir, seq)
+        .map(|column| match column {
+            (Some(l), Some(r)) if matrix.wobble_u8(l, pair(r)) => wobble,
+            (Some(l), Some(r)) if matrix.match_u8(l, pair(r)) => yes,
+            (Some(_), Some(_)) => no,
+            _ => unpaired,
+        })
+        .collect();
+    out.write_all(&matching)
+}
+
+/// Number of wobble pairs between the arms, which are not counted as mismatches.
+fn count_wobbles(
+    ir: &InvertedRepeat,
+    seq: &Sequence,
+    matrix: &MatchMatrix,
+    complement: &[u8; 128],
+) -> usize {
+    arm_columns(ir, seq)
+        .filter(|column| match *column {
+            (Some(l), Some(r)) => matrix.wobble_u8(l, paired_symbol(r, seq.kind, complement)),
+            _ => false,
+        })
+        .count()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::SearchParams;
-    use crate::{constants::build_complement_array, find_irs, matrix};
+    use crate::{constants::build_complement_array, find_irs};
+
+    fn fmt_irs(format: &OutputFormat, irs: &[InvertedRepeat], seq: &[u8]) -> String {
+        let config = Config {
+            output_format: format.clone(),
+            ..Default::default()
+        };
+        fmt_irs_with(&config, irs, &Sequence::new(seq, &config.params))
+    }
+
+    fn fmt_irs_with(config: &Config, irs: &[InvertedRepeat], seq: &Sequence) -> String {
+        let format = &config.output_format;
+        let matrix = config.params.matrix();
+        let complement = build_complement_array();
+        let mut out = Vec::new();
+        for (nth, ir) in irs.iter().enumerate() {
+            out.extend_from_slice(fmt_separator(format, nth).as_bytes());
+            let score = config.score.then(|| seq.score(ir, &config.score_weights));
+            write_ir(&mut out, config, ir, nth, seq, score, &matrix, &complement).unwrap();
+        }
+        out.extend_from_slice(fmt_footer(format, irs.len()).as_bytes());
+        String::from_utf8(out).unwrap()
+    }
 
     #[test]
     fn test_format_classic() {
@@ -182,9 +889,7 @@ mod tests {
         let params = SearchParams::new(10, 100, 10, 1).unwrap();
         params.check_bounds(seq.len()).unwrap();
         let irs = find_irs(&params, &seq).unwrap();
-        let matrix = matrix::MatchMatrix::new();
-        let complement = build_complement_array();
-        let received = fmt_classic(&irs, &seq, &matrix, &complement);
+        let received = fmt_irs(&OutputFormat::Classic, &irs, &seq);
         let expected = r"2        gucsggtgtwkmmm       15
          ||| ||||||||||
 30       nngah*nn-nddbk       17
@@ -243,6 +948,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_classic_header_regions() {
+        let mut config = Config::default();
+        let header = fmt_classic_header(&config, 100);
+        assert!(header.contains("Start at position: 1\nEnd at position: 100\n"));
+
+        config.regions = Some(vec![9..20, 40..60]);
+        let header = fmt_classic_header(&config, 100);
+        assert!(header.contains("Start at position: 10\nEnd at position: 60\n"));
+    }
+
     #[test]
     fn test_format_csv() {
         let string = "AGUCSGGTGTWKMMMKKBDDN-NN*HAGNNAGuGTA";
@@ -250,12 +966,10 @@ mod tests {
         let params = SearchParams::new(10, 100, 10, 1).unwrap();
         params.check_bounds(seq.len()).unwrap();
         let irs = find_irs(&params, &seq).unwrap();
-        let matrix = matrix::MatchMatrix::new();
-        let complement = build_complement_array();
         let received = format!(
             "{}\n{}",
-            fmt_csv_header(),
-            fmt_csv(&irs, &seq, &matrix, &complement)
+            fmt_csv_header(&Config::default()),
+            fmt_irs(&OutputFormat::Csv, &irs, &seq)
         );
         let expected = r"start_n,end_n,nucleotide,start_ir,end_ir,reverse_complement,matching
 2,15,gucsggtgtwkmmm,30,17,nngah*nn-nddbk,11101111111111
@@ -286,7 +1000,11 @@ mod tests {
         let params = SearchParams::new(10, 100, 10, 1).unwrap();
         params.check_bounds(seq.len()).unwrap();
         let irs = find_irs(&params, &seq).unwrap();
-        let received = format!("{}\n{}", fmt_custom_header(), fmt_custom(&irs, &seq));
+        let received = format!(
+            "{}\n{}",
+            fmt_custom_header(&Config::default()),
+            fmt_irs(&OutputFormat::Custom, &irs, &seq)
+        );
         let expected = r"ir_start,motif,gap_motif,reverse_complement
 2,gucsggtgtwkmmm,k,nngah*nn-nddbk
 3,ucsggtgtwkmmm,kk,nngah*nn-nddb
@@ -308,4 +1026,493 @@ mod tests {
             assert_eq!(e, r, "Difference at line {idx}");
         }
     }
+
+    #[test]
+    fn test_format_bed() {
+        let seq = b"acbbgtaaacbbgt";
+        let params = SearchParams::new(3, 6, 2, 0).unwrap();
+        let irs = find_irs(&params, seq).unwrap();
+
+        let received = fmt_irs(&OutputFormat::Bed, &irs[..2], seq);
+        // The score is relative to the default max_len of 100
+        let expected = "seq0\t0\t14\tir1\t60\t.\nseq0\t0\t6\tir2\t30\t.\n";
+        assert_eq!(received, expected);
+
+        let received = fmt_irs(&OutputFormat::Bed12, &irs[..2], seq);
+        let expected = "\
+            seq0\t0\t14\tir1\t60\t.\t0\t14\t0\t2\t6,6\t0,8\n\
+            seq0\t0\t6\tir2\t30\t.\t0\t6\t0\t2\t3,3\t0,3\n";
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_bed_score() {
+        let mut ir = InvertedRepeat::new(0, 21, 2);
+        assert_eq!(bed_score(&ir, 10), 1000);
+        ir.mismatch_positions = vec![3, 5];
+        assert_eq!(bed_score(&ir, 10), 800);
+        assert_eq!(bed_score(&ir, 100), 80);
+    }
+
+    #[test]
+    fn test_format_gff3() {
+        let seq = b"acbbgtaaacbbgt";
+        let params = SearchParams::new(3, 6, 2, 0).unwrap();
+        let irs = find_irs(&params, seq).unwrap();
+        let config = Config {
+            seq_name: "chr 1",
+            output_format: OutputFormat::Gff3,
+            ..Default::default()
+        };
+        let received = format!(
+            "{}\n{}",
+            fmt_header(&config, seq.len()),
+            fmt_irs(&OutputFormat::Gff3, &irs[..2], seq)
+        );
+        // fmt_irs uses seq0 as sequence name
+        let expected = "\
+            ##gff-version 3\n\
+            ##sequence-region chr%201 1 14\n\
+            seq0\tiirs\tinverted_repeat\t1\t14\t.\t.\t.\tID=ir1;gap=2;mismatches=0;matching=111111\n\
+            seq0\tiirs\trepeat_arm\t1\t6\t.\t.\t.\tID=ir1.arm1;Parent=ir1\n\
+            seq0\tiirs\tloop\t7\t8\t.\t.\t.\tID=ir1.loop;Parent=ir1\n\
+            seq0\tiirs\trepeat_arm\t9\t14\t.\t.\t.\tID=ir1.arm2;Parent=ir1\n\
+            seq0\tiirs\tinverted_repeat\t1\t6\t.\t.\t.\tID=ir2;gap=0;mismatches=0;matching=111\n\
+            seq0\tiirs\trepeat_arm\t1\t3\t.\t.\t.\tID=ir2.arm1;Parent=ir2\n\
+            seq0\tiirs\trepeat_arm\t4\t6\t.\t.\t.\tID=ir2.arm2;Parent=ir2\n";
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_gff3_escape() {
+        assert_eq!(gff3_escape("chr1.a|b", GFF3_SEQID_SAFE), "chr1.a|b");
+        assert_eq!(
+            gff3_escape("a b;c=d\t%", GFF3_SEQID_SAFE),
+            "a%20b%3Bc%3Dd%09%25"
+        );
+        assert_eq!(gff3_escape("ñ", GFF3_SEQID_SAFE), "%C3%B1");
+    }
+
+    #[test]
+    fn test_json_str() {
+        assert_eq!(json_str("seq0"), r#""seq0""#);
+        assert_eq!(json_str("a\"b\\c\td\u{1}"), r#""a\"b\\c\td\u0001""#);
+        assert_eq!(json_str("ñ"), r#""ñ""#);
+    }
+
+    #[test]
+    fn test_format_json() {
+        let seq = b"acbbgtaaacbbgt";
+        let params = SearchParams::new(3, 6, 2, 0).unwrap();
+        let irs = find_irs(&params, seq).unwrap();
+        let config = Config {
+            seq_name: "chr1 \"x\"",
+            params,
+            output_format: OutputFormat::Json,
+            ..Default::default()
+        };
+        let received = format!(
+            "{}\n{}",
+            fmt_header(&config, seq.len()),
+            fmt_irs(&OutputFormat::Json, &irs[..2], seq)
+        );
+        let expected = r#"{"config":{"input_file":"input.fasta","seq_name":"chr1 \"x\"","min_len":3,"max_len":6,"max_gap":2,"mismatches":0},"sequence":{"name":"chr1 \"x\"","length":14},"irs":[
+{"start":1,"end":14,"arm_length":6,"gap":2,"mismatches":0,"left_arm":{"start":1,"end":6,"seq":"acbbgt"},"loop":"aa","right_arm":{"start":9,"end":14,"seq":"acbbgt"},"matching":"111111","mismatch_positions":[]},
+{"start":1,"end":6,"arm_length":3,"gap":0,"mismatches":0,"left_arm":{"start":1,"end":3,"seq":"acb"},"loop":"","right_arm":{"start":4,"end":6,"seq":"bgt"},"matching":"111","mismatch_positions":[]}
+]}"#;
+        assert_eq!(received, expected);
+
+        let empty = format!(
+            "{}\n{}",
+            fmt_header(&config, 0),
+            fmt_irs(&OutputFormat::Json, &[], seq)
+        );
+        assert!(empty.ends_with("\"irs\":[\n]}"));
+    }
+
+    #[test]
+    fn test_format_jsonl() {
+        let string = "AGUCSGGTGTWKMMMKKBDDN-NN*HAGNNAGuGTA";
+        let seq = string.to_ascii_lowercase().as_bytes().to_vec();
+        let params = SearchParams::new(10, 100, 10, 1).unwrap();
+        let irs = find_irs(&params, &seq).unwrap();
+        let received = fmt_irs(&OutputFormat::Jsonl, &irs, &seq);
+        assert_eq!(received.lines().count(), irs.len());
+        let first = received.lines().next().unwrap();
+        let expected = r#"{"seq_name":"seq0","start":2,"end":30,"arm_length":14,"gap":1,"mismatches":1,"left_arm":{"start":2,"end":15,"seq":"gucsggtgtwkmmm"},"loop":"k","right_arm":{"start":17,"end":30,"seq":"kbddn-nn*hagnn"},"matching":"11101111111111","mismatch_positions":[5]}"#;
+        assert_eq!(first, expected);
+    }
+
+    #[test]
+    fn test_format_json_features() {
+        let string = "AGUCSGGTGTWKMMMKKBDDN-NN*HAGNNAGuGTA";
+        let seq = string.to_ascii_lowercase().as_bytes().to_vec();
+        let params = SearchParams::new(10, 100, 10, 1).unwrap();
+        let ir = &find_irs(&params, &seq).unwrap()[0];
+        assert_eq!((ir.left, ir.right), (1, 29));
+
+        let feature = |start, end, gene: &str| Feature {
+            kind: String::from("gene"),
+            start,
+            end,
+            qualifiers: vec![(String::from("gene"), gene.to_string())],
+        };
+        let features = [
+            feature(0, 1, "before"),
+            feature(10, 12, "inside"),
+            feature(29, 40, "last\"base"),
+            feature(30, 40, "after"),
+        ];
+        let config = Config {
+            output_format: OutputFormat::Json,
+            ..Default::default()
+        };
+        let sequence = Sequence::new(&seq, &params).with_features(&features);
+        let out = fmt_irs_with(&config, std::slice::from_ref(ir), &sequence);
+        assert!(
+            out.contains(r#""mismatch_positions":[5],"features":["inside","last\"base"]}"#),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn test_format_rna() {
+        // The innermost pair of the arms is a g·u wobble pair.
+        let seq = b"ccgacgaaaatgtccgg";
+        let mut params = SearchParams::new(4, 10, 4, 0).unwrap();
+        assert!(find_irs(&params, seq).unwrap().is_empty());
+
+        params.rna = true;
+        let irs = find_irs(&params, seq).unwrap();
+        assert_eq!(irs.len(), 1);
+        let expected = [
+            (
+                OutputFormat::Classic,
+                "3        gacg        6\n         |||:\n14       cugu       11\n\n",
+            ),
+            (OutputFormat::Csv, "3,6,gacg,14,11,cugu,111w\n"),
+            (OutputFormat::Custom, "3,gacg,aaaa,cugu\n"),
+        ];
+        for (output_format, expected) in expected {
+            let config = Config {
+                params: params.clone(),
+                output_format,
+                ..Default::default()
+            };
+            let received = fmt_irs_with(&config, &irs, &Sequence::new(seq, &params));
+            assert_eq!(received, expected);
+        }
+
+        let config = Config {
+            params: params.clone(),
+            output_format: OutputFormat::Jsonl,
+            ..Default::default()
+        };
+        let received = fmt_irs_with(&config, &irs, &Sequence::new(seq, &params));
+        assert!(
+            received.contains("\"matching\":\"111w\",\"mismatch_positions\":[],\"wobbles\":1}")
+        );
+    }
+
+    #[test]
+    fn test_format_soft_mask() {
+        let raw = b"acgTTTTacgt";
+        let soft_mask = SoftMask::from_seq(raw).unwrap();
+        let seq = raw.to_ascii_lowercase();
+        let params = SearchParams::new(4, 10, 4, 0).unwrap();
+        let irs = find_irs(&params, &seq).unwrap();
+        assert_eq!(irs.len(), 1);
+
+        let fmt = |output_format, soft_mask| {
+            let config = Config {
+                params: params.clone(),
+                output_format,
+                soft_mask: true,
+                ..Default::default()
+            };
+            let sequence = Sequence::new(&seq, &params).with_soft_mask(soft_mask);
+            fmt_irs_with(&config, &irs, &sequence)
+        };
+        let config = Config {
+            soft_mask: true,
+            ..Default::default()
+        };
+        assert!(fmt_csv_header(&config).ends_with(",matching,masked_fraction"));
+        // 3 + 4 of the 8 nucleotides of the arms are in lowercase
+        let csv = fmt(OutputFormat::Csv, Some(&soft_mask));
+        assert_eq!(csv, "1,4,acgt,11,8,tgca,1111,0.875\n");
+        assert_eq!(
+            fmt(OutputFormat::Csv, None),
+            "1,4,acgt,11,8,tgca,1111,0.000\n"
+        );
+        let json = fmt(OutputFormat::Jsonl, Some(&soft_mask));
+        assert!(json.contains(r#""mismatch_positions":[],"masked_fraction":0.875}"#));
+        // Not reported in the other formats
+        let custom = fmt(OutputFormat::Custom, Some(&soft_mask));
+        assert_eq!(custom, "1,acgt,ttt,tgca\n");
+    }
+
+    #[test]
+    fn test_format_score() {
+        let seq = b"acgnttacgt";
+        let params = SearchParams::new(4, 10, 2, 0).unwrap();
+        let irs = find_irs(&params, seq).unwrap();
+        assert_eq!(irs.len(), 1);
+
+        let weights = ScoreWeights {
+            gap: -1.0,
+            ..Default::default()
+        };
+        let fmt = |output_format, score_weights: Option<ScoreWeights>| {
+            let config = Config {
+                params: params.clone(),
+                output_format,
+                score: score_weights.is_some(),
+                score_weights: score_weights.unwrap_or_default(),
+                ..Default::default()
+            };
+            fmt_irs_with(&config, &irs, &Sequence::new(seq, &params))
+        };
+        let config = Config {
+            score: true,
+            ..Default::default()
+        };
+        assert!(fmt_csv_header(&config).ends_with(",matching,score"));
+        // 4 matching pairs, an n and a gap of 2
+        let csv = fmt(OutputFormat::Csv, Some(weights));
+        assert_eq!(csv, "1,4,acgn,10,7,tgca,1111,1.50\n");
+        let json = fmt(OutputFormat::Jsonl, Some(weights));
+        assert!(json.contains(r#""mismatch_positions":[],"score":1.50}"#));
+        let bed = fmt(OutputFormat::Bed, Some(weights));
+        assert_eq!(bed, "seq0\t0\t10\tir1\t2\t.\n");
+        // Negative scores are clamped
+        let negative = ScoreWeights {
+            gap: -10.0,
+            ..weights
+        };
+        let bed = fmt(OutputFormat::Bed, Some(negative));
+        assert_eq!(bed, "seq0\t0\t10\tir1\t0\t.\n");
+        // Without weights, the bed score is the default one
+        let bed = fmt(OutputFormat::Bed, None);
+        assert_eq!(bed, "seq0\t0\t10\tir1\t400\t.\n");
+    }
+
+    #[test]
+    fn test_format_edits() {
+        // The right arm has an extra a: gcatgc-aatc / gcatgcgattag
+        let seq = b"ttttgcatgcaatcccccgattagcatgcgggg";
+        let mut params = SearchParams::new(6, 20, 6, 0).unwrap();
+        params.max_edits = Some(1);
+        let irs = find_irs(&params, seq).unwrap();
+        assert_eq!(irs[0].as_tuple(), (4, 28, 4));
+
+        let fmt = |output_format| {
+            let config = Config {
+                params: params.clone(),
+                output_format,
+                ..Default::default()
+            };
+            fmt_irs_with(&config, &irs[..1], &Sequence::new(seq, &params))
+        };
+        let config = Config {
+            params: params.clone(),
+            ..Default::default()
+        };
+        assert!(fmt_csv_header(&config).ends_with(",matching,cigar"));
+        let expected = r"5        gcatgc-aatc       14
+         |||||| ||||
+29       cgtacgattag       19
+
+";
+        assert_eq!(fmt(OutputFormat::Classic), expected);
+        assert_eq!(
+            fmt(OutputFormat::Csv),
+            "5,14,gcatgcaatc,29,19,cgtacgattag,111111-1111,6=1D4=\n"
+        );
+        let json = fmt(OutputFormat::Jsonl);
+        assert!(json.contains(r#""edits":1,"cigar":"6=1D4="}"#), "{json}");
+        let gff3 = fmt(OutputFormat::Gff3);
+        assert!(gff3.contains("edits=1;cigar=6=1D4=;"), "{gff3}");
+        // The blocks of the arms have different lengths
+        assert_eq!(
+            fmt(OutputFormat::Bed12),
+            "seq0\t4\t29\tir1\t450\t.\t4\t29\t0\t2\t10,11\t0,14\n"
+        );
+    }
+
+    #[test]
+    fn test_format_kinds() {
+        let fmt = |seq: &[u8], kind, output_format| {
+            let mut params = SearchParams::new(4, 10, 2, 1).unwrap();
+            params.kind = kind;
+            let irs = find_irs(&params, seq).unwrap();
+            let config = Config {
+                params: params.clone(),
+                output_format,
+                ..Default::default()
+            };
+            fmt_irs_with(&config, &irs, &Sequence::new(seq, &params))
+        };
+
+        // The right arm is written as is, from its start
+        let seq = b"ttacgtcaggacctcatt";
+        let expected = r"3        acgtca        8
+         || |||
+11       acctca       16
+
+";
+        assert_eq!(
+            fmt(seq, RepeatKind::Direct, OutputFormat::Classic),
+            expected
+        );
+        assert_eq!(
+            fmt(seq, RepeatKind::Direct, OutputFormat::Csv),
+            "3,8,acgtca,11,16,acctca,110111\n"
+        );
+        assert_eq!(
+            fmt(seq, RepeatKind::Direct, OutputFormat::Custom),
+            "3,acgtca,gg,acctca\n"
+        );
+        let gff3 = fmt(seq, RepeatKind::Direct, OutputFormat::Gff3);
+        assert!(
+            gff3.starts_with("seq0\tiirs\tdirect_repeat\t3\t16\t"),
+            "{gff3}"
+        );
+
+        // The right arm is reversed, but not complemented
+        let seq = b"ttacgtcgacgctgcatt";
+        assert_eq!(
+            fmt(seq, RepeatKind::Mirror, OutputFormat::Csv),
+            "1,8,ttacgtcg,18,11,ttacgtcg,11111111\n7,10,cgac,15,12,cgtc,1101\n"
+        );
+        let config = Config {
+            params: SearchParams {
+                kind: RepeatKind::Mirror,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(fmt_csv_header(&config).ends_with(",end_ir,reverse,matching"));
+        assert!(fmt_json_header(&config, 18).contains(r#""mismatches":0,"kind":"mirror"}"#));
+    }
+
+    #[test]
+    fn test_json_header_params() {
+        let config = Config {
+            params: SearchParams {
+                max_edits: Some(2),
+                report: ReportMode::Maximal,
+                circular: true,
+                rna: true,
+                match_mode: MatchMode::Strict,
+                n_policy: NPolicy::Break,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(fmt_json_header(&config, 18).contains(
+            r#""mismatches":0,"max_edits":2,"report":"maximal","circular":true,"rna":true,"match_mode":"strict","n_policy":"break"}"#
+        ));
+
+        let matrix =
+            MatchMatrix::parse("a c g t\na 0 0 0 1\nc 0 0 1 0\ng 0 1 0 0\nt 1 0 0 0").unwrap();
+        let config = Config {
+            params: SearchParams {
+                match_mode: MatchMode::Custom(std::sync::Arc::new(matrix)),
+                ..Default::default()
+            },
+            regions: Some(vec![0..10, 20..30]),
+            window_size: Some(1000),
+            soft_mask: true,
+            max_masked_fraction: Some(0.5),
+            min_score: Some(-2.5),
+            sort_by_score: true,
+            ..Default::default()
+        };
+        let header = fmt_json_header(&config, 40);
+        assert!(
+            header.contains(
+                r#""mismatches":0,"match_mode":"custom","match_pairs":["at","cg"],"regions":[[1,10],[21,30]],"window_size":1000,"soft_mask":true,"max_masked_fraction":0.5,"score_weights":{"match":1,"mismatch":-1,"indel":-2,"ambiguous":-0.5,"gap":-0.1},"min_score":-2.5,"sort_by":"score"}"#
+            ),
+            "{header}"
+        );
+    }
+
+    #[test]
+    fn test_format_circular() {
+        // The IR acgt wraps around the origin
+        let seq = b"gtaaaaac";
+        let mut params = SearchParams::new(2, 4, 0, 0).unwrap();
+        params.circular = true;
+        let irs = find_irs(&params, seq).unwrap();
+        assert_eq!(irs[0].as_tuple(), (6, 9, 0));
+
+        let fmt = |output_format, features: &[Feature]| {
+            let config = Config {
+                params: params.clone(),
+                output_format,
+                ..Default::default()
+            };
+            let sequence = Sequence::new(seq, &params).with_features(features);
+            fmt_irs_with(&config, &irs, &sequence)
+        };
+        assert_eq!(fmt(OutputFormat::Csv, &[]), "7,8,ac,2,1,tg,11\n");
+        assert_eq!(fmt(OutputFormat::Custom, &[]), "7,ac,,tg\n");
+        // Split at the origin
+        assert_eq!(
+            fmt(OutputFormat::Bed, &[]),
+            "seq0\t6\t8\tir1\t500\t.\nseq0\t0\t2\tir1\t500\t.\n"
+        );
+        assert_eq!(
+            fmt(OutputFormat::Bed12, &[]),
+            "seq0\t6\t8\tir1\t500\t.\t6\t8\t0\t1\t2\t0\n\
+            seq0\t0\t2\tir1\t500\t.\t0\t2\t0\t1\t2\t0\n"
+        );
+
+        let features = [Feature {
+            kind: String::from("gene"),
+            start: 0,
+            end: 1,
+            qualifiers: Vec::new(),
+        }];
+        let json = fmt(OutputFormat::Jsonl, &features);
+        assert!(json.contains(r#""start":7,"end":2,"#), "{json}");
+        assert!(
+            json.contains(r#""right_arm":{"start":1,"end":2,"seq":"gt"}"#),
+            "{json}"
+        );
+        assert!(
+            json.trim_end().ends_with(r#""features":["gene"]}"#),
+            "{json}"
+        );
+    }
+
+    #[test]
+    fn test_format_bed_circular_loop() {
+        // acg t acg wraps around the origin of tacgaacg in its loop: the bed12 features
+        // only span their arm
+        let seq = b"tacgaacg";
+        let mut params = SearchParams::new(3, 3, 1, 0).unwrap();
+        params.circular = true;
+        let irs = [InvertedRepeat::new(5, 11, 1)];
+        let config = Config {
+            params: params.clone(),
+            output_format: OutputFormat::Bed,
+            ..Default::default()
+        };
+        let sequence = Sequence::new(seq, &params);
+        assert_eq!(
+            fmt_irs_with(&config, &irs, &sequence),
+            "seq0\t5\t8\tir1\t1000\t.\nseq0\t0\t4\tir1\t1000\t.\n"
+        );
+        let config = Config {
+            output_format: OutputFormat::Bed12,
+            ..config
+        };
+        assert_eq!(
+            fmt_irs_with(&config, &irs, &sequence),
+            "seq0\t5\t8\tir1\t1000\t.\t5\t8\t0\t1\t3\t0\n\
+            seq0\t1\t4\tir1\t1000\t.\t1\t4\t0\t1\t3\t0\n"
+        );
+    }
 }
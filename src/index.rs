@@ -0,0 +1,770 @@
+use anyhow::{Result, anyhow, bail};
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::algo::{Reached, SaIndex};
+use crate::chunk::{check_regions, find_irs_in_regions_with, find_irs_whole, find_irs_whole_with};
+use crate::config::SearchParams;
+use crate::ir::{InvertedRepeat, RepeatKind};
+use crate::iter::IrIter;
+use crate::matrix::MatchMatrix;
+use crate::{algo, constants, utils};
+
+/// Index over a sequence that can answer IR searches for many [`SearchParams`].
+///
+/// Building the index (suffix array, LCP and RMQ) is the expensive part of [`crate::find_irs`],
+/// and it does not depend on the search parameters. When scanning the same sequence
+/// multiple times, build the index once and [`search`](IrIndex::search) it.
+///
+/// The index can also be persisted with [`save`](IrIndex::save) and memory mapped
+/// with [`load`](IrIndex::load), to avoid paying the construction cost on every run.
+///
+/// # Examples
+///
+/// ```rust
+/// use iirs::{IrIndex, SearchParams};
+///
+/// let index = IrIndex::build("acbbgt".as_bytes()).unwrap();
+/// for max_gap in 0..3 {
+///     let params = SearchParams::new(3, 6, max_gap, 0).unwrap();
+///     let irs = index.search(&params).unwrap();
+///     assert_eq!(irs.len(), 1);
+/// }
+/// ```
+pub struct IrIndex {
+    /// `s = seq + '$' + complement(reverse(seq)) + '#'`, or without complement for a
+    /// mirror index (see [`IrIndex::build_for`]).
+    s: Buf<u8>,
+    mirror: bool,
+    inv_sa: Buf<usize>,
+    // By default use the Sparse Table implementation for the Rmq.
+    #[cfg(not(feature = "tabulation"))]
+    rmq: SparseTable,
+    // The Tabulation implementation borrows the LCP array, so we store the latter
+    // and build the (linear) Tabulation on every search.
+    #[cfg(feature = "tabulation")]
+    lcp: Buf<usize>,
+}
+
+impl IrIndex {
+    /// Build the index of the given sequence.
+    ///
+    /// Returns an error if the sequence contains invalid characters.
+    pub fn build(seq: &[u8]) -> Result<Self> {
+        Self::build_text(seq, false)
+    }
+
+    /// Build the index needed by a search with `params`: the mirror repeats pair the
+    /// sequence with its reverse, without complement, so they need their own index.
+    /// The other kinds use the one of [`IrIndex::build`].
+    pub(crate) fn build_for(seq: &[u8], params: &SearchParams) -> Result<Self> {
+        Self::build_text(seq, params.kind == RepeatKind::Mirror)
+    }
+
+    fn build_text(seq: &[u8], mirror: bool) -> Result<Self> {
+        // Removes newlines, cast to lowercase and checks that all the character are in IUPAC.
+        // This was already done through the CLI, but we need to do it again for the standalone version.
+        let seq = utils::sanitize_sequence(seq)?;
+        let mut complement = constants::build_complement_array();
+        // The reverse is not complemented for the mirror repeats
+        if mirror {
+            for (byte, value) in complement.iter_mut().enumerate() {
+                *value = byte as u8;
+            }
+        }
+
+        // Construct s = seq + '$' + complement(reverse(seq)) + '#'
+        let n = seq.len();
+        let s_n = 2 * n + 2;
+        let mut s = vec![0u8; s_n];
+        for i in 0..n {
+            s[i] = seq[i];
+            s[n + 1 + i] = complement[seq[n - 1 - i] as usize];
+        }
+        s[n] = b'$';
+        s[2 * n + 1] = b'#';
+
+        // Construct Suffix Array (sa), Inverse Suffix Array & LCP
+        let (inv_sa, lcp) = if i32::try_from(s_n).is_ok() {
+            inv_sa_and_lcp(&s, &divsufsort::sort(&s).into_parts().1)
+        } else {
+            inv_sa_and_lcp(&s, &suffix_array_64(&s)?)
+        };
+
+        // Calculate RMQ
+        #[cfg(not(feature = "tabulation"))]
+        let rmq = SparseTable::new(&lcp);
+
+        Ok(Self {
+            s: Buf::Owned(s),
+            mirror,
+            inv_sa: Buf::Owned(inv_sa),
+            #[cfg(not(feature = "tabulation"))]
+            rmq,
+            #[cfg(feature = "tabulation")]
+            lcp: Buf::Owned(lcp),
+        })
+    }
+
+    /// The sanitized sequence this index was built from.
+    ///
+    /// The positions of the IRs returned by [`search`](IrIndex::search) refer to it.
+    pub fn seq(&self) -> &[u8] {
+        &self.s[..self.s.len() / 2 - 1]
+    }
+
+    /// Whether a search with `params` can use this index. Otherwise, the sequence is
+    /// searched without it: the index is linear, and it does not pair the sequence with
+    /// its reverse for the mirror repeats.
+    fn supports(&self, params: &SearchParams) -> bool {
+        !params.circular && self.mirror == (params.kind == RepeatKind::Mirror)
+    }
+
+    /// Find all the IRs in the sequence based on the provided parameters.
+    ///
+    /// The IRs are sorted in the same way as [`crate::find_irs`].
+    ///
+    /// The index is linear and built for inverted (and direct) repeats: for a circular
+    /// search (`params.circular`) or one of mirror repeats, the sequence is searched again
+    /// without it.
+    ///
+    /// Returns an error if `params` is not supported (see [`SearchParams::check_supported`]).
+    pub fn search(&self, params: &SearchParams) -> Result<Vec<InvertedRepeat>> {
+        if !self.supports(params) {
+            return find_irs_whole(params, self.seq());
+        }
+        params.check_supported()?;
+        let mut irs = self.search_centers(params, 0..self.s.len(), &Reached::default());
+        sort_irs(&mut irs);
+        Ok(irs)
+    }
+
+    /// Find all the IRs in the sequence based on the provided parameters, calling `f` on
+    /// each of them instead of collecting them.
+    ///
+    /// The IRs are passed to `f` in the same order as [`search`](IrIndex::search) returns them,
+    /// but only a bounded amount of them is held in memory at any time. Stops at the first
+    /// error returned by `f`.
+    ///
+    /// As for [`search`](IrIndex::search), a circular or mirror search does not use the index.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use iirs::{IrIndex, SearchParams};
+    ///
+    /// let index = IrIndex::build("acbbgtttacbbgt".as_bytes()).unwrap();
+    /// let params = SearchParams::new(3, 6, 2, 0).unwrap();
+    /// let mut irs = Vec::new();
+    /// index.search_with(&params, |ir| {
+    ///     irs.push(ir);
+    ///     Ok(())
+    /// }).unwrap();
+    /// assert_eq!(irs, index.search(&params).unwrap());
+    /// ```
+    pub fn search_with<F>(&self, params: &SearchParams, f: F) -> Result<()>
+    where
+        F: FnMut(InvertedRepeat) -> Result<()>,
+    {
+        if !self.supports(params) {
+            return find_irs_whole_with(params, self.seq(), f);
+        }
+        params.check_supported()?;
+        self.search_center_ranges_with(params, std::iter::once(0..self.s.len()), f)
+    }
+
+    /// Same as [`search_with`](IrIndex::search_with), only for the IRs centered in the
+    /// given regions of the sequence (see [`crate::find_irs_in_regions`]).
+    ///
+    /// Returns an error if the regions are not sorted, overlap or go past the end of the
+    /// sequence.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use iirs::{IrIndex, SearchParams, find_irs_in_regions};
+    ///
+    /// let seq = "acbbgtttacbbgt".as_bytes();
+    /// let index = IrIndex::build(seq).unwrap();
+    /// let params = SearchParams::new(3, 6, 2, 0).unwrap();
+    /// let mut irs = Vec::new();
+    /// index.search_regions_with(&params, &[8..14], |ir| {
+    ///     irs.push(ir);
+    ///     Ok(())
+    /// }).unwrap();
+    /// assert_eq!(irs, find_irs_in_regions(&params, seq, &[8..14]).unwrap());
+    /// ```
+    pub fn search_regions_with<F>(
+        &self,
+        params: &SearchParams,
+        regions: &[Range<usize>],
+        f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(InvertedRepeat) -> Result<()>,
+    {
+        if !self.supports(params) {
+            return find_irs_in_regions_with(params, self.seq(), regions, f);
+        }
+        params.check_supported()?;
+        check_regions(regions, self.seq().len())?;
+        let centers = regions
+            .iter()
+            .map(|region| 2 * region.start..2 * region.end);
+        self.search_center_ranges_with(params, centers, f)
+    }
+
+    /// Search the sorted, non overlapping ranges of (doubled) centers in batches, passing
+    /// the IRs to `f` in order.
+    fn search_center_ranges_with<F>(
+        &self,
+        params: &SearchParams,
+        ranges: impl Iterator<Item = Range<usize>>,
+        f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(InvertedRepeat) -> Result<()>,
+    {
+        let matrix = params.matrix();
+        #[cfg(not(feature = "tabulation"))]
+        let rmq = &self.rmq;
+        #[cfg(feature = "tabulation")]
+        let rmq = &rmq::Tabulation::new(&self.lcp);
+
+        let mut ordered = OrderedIrs::new(params, f);
+        let batches = ranges.flat_map(|range| {
+            range
+                .clone()
+                .step_by(CENTERS_PER_BATCH)
+                .map(move |start| start..(start + CENTERS_PER_BATCH).min(range.end))
+        });
+        for centers in batches {
+            let batch_end = centers.end;
+            let irs = algo::add_irs_in_centers(
+                &self.s,
+                &self.inv_sa,
+                rmq,
+                params,
+                &matrix,
+                centers,
+                &Reached::default(),
+            );
+            ordered.push(irs, batch_end)?;
+        }
+        ordered.finish()
+    }
+
+    /// Lazily find the IRs in the sequence, see [`crate::find_irs_iter`].
+    ///
+    /// # Panics
+    ///
+    /// If `params.circular` is set: the iterators only support linear sequences. Or if
+    /// `params.kind` is mirror, which this index does not support (use
+    /// [`crate::find_irs_iter`] instead). Or if `params` is not supported by any search
+    /// (see [`SearchParams::check_supported`]).
+    pub fn iter(&self, params: &SearchParams) -> IrIter<&Self> {
+        assert!(!params.circular, "circular sequences can not be iterated");
+        assert!(self.supports(params), "mirror repeats need their own index");
+        assert!(
+            params.check_supported().is_ok(),
+            "max_edits is not supported for direct repeats"
+        );
+        IrIter::new(self, params)
+    }
+
+    /// Find the IRs in the sequence in parallel, see [`crate::find_irs_par_iter`].
+    ///
+    /// # Panics
+    ///
+    /// If `params.circular` is set: the iterators only support linear sequences. Or if
+    /// `params.kind` is mirror, or if `params` is not supported, as for
+    /// [`iter`](IrIndex::iter).
+    #[cfg(feature = "parallel")]
+    pub fn par_iter<'a>(
+        &'a self,
+        params: &SearchParams,
+    ) -> impl rayon::iter::ParallelIterator<Item = InvertedRepeat> + use<'a> {
+        assert!(!params.circular, "circular sequences can not be iterated");
+        assert!(self.supports(params), "mirror repeats need their own index");
+        assert!(
+            params.check_supported().is_ok(),
+            "max_edits is not supported for direct repeats"
+        );
+        crate::iter::par_iter(self, params)
+    }
+
+    /// Find, without sorting, the IRs whose (doubled) center `left + right` is in `centers`.
+    pub(crate) fn search_centers(
+        &self,
+        params: &SearchParams,
+        centers: Range<usize>,
+        reached: &Reached,
+    ) -> Vec<InvertedRepeat> {
+        let matrix = params.matrix();
+        self.search_centers_in(params, &matrix, centers, reached)
+    }
+
+    /// Same as [`search_centers`](IrIndex::search_centers), with a prebuilt `matrix`.
+    ///
+    /// With the `tabulation` feature, this builds the RMQ in linear time on every call.
+    pub(crate) fn search_centers_in(
+        &self,
+        params: &SearchParams,
+        matrix: &MatchMatrix,
+        centers: Range<usize>,
+        reached: &Reached,
+    ) -> Vec<InvertedRepeat> {
+        #[cfg(not(feature = "tabulation"))]
+        let rmq = &self.rmq;
+        #[cfg(feature = "tabulation")]
+        let rmq = &rmq::Tabulation::new(&self.lcp);
+        algo::add_irs_in_centers(&self.s, &self.inv_sa, rmq, params, matrix, centers, reached)
+    }
+
+    /// Write the index to `path`.
+    ///
+    /// The file layout is (every integer is a little-endian `u64`):
+    ///
+    /// ```text
+    /// magic    b"IIRSIDX\0"
+    /// version  INDEX_VERSION
+    /// n        sequence length
+    /// lgn      number of columns of the sparse table
+    /// s        2n + 2 bytes, zero padded to a multiple of 8
+    /// inv_sa   2n + 2 integers
+    /// lcp      2n + 2 integers
+    /// table    (2n + 2) * lgn integers (sparse table for the RMQ)
+    /// ```
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let s_n = self.s.len();
+        #[cfg(not(feature = "tabulation"))]
+        let (lcp, rmq) = (Cow::Owned(self.rmq.base()), &self.rmq);
+        #[cfg(feature = "tabulation")]
+        let (lcp, rmq) = (Cow::Borrowed(&self.lcp[..]), &SparseTable::new(&self.lcp));
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(INDEX_MAGIC)?;
+        for value in [INDEX_VERSION, s_n / 2 - 1, rmq.lgn] {
+            writer.write_all(&(value as u64).to_le_bytes())?;
+        }
+        writer.write_all(&self.s)?;
+        writer.write_all(&vec![0; padding(s_n)])?;
+        for values in [&self.inv_sa[..], &*lcp, &rmq.table[..]] {
+            for value in values {
+                writer.write_all(&(*value as u64).to_le_bytes())?;
+            }
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Memory map an index previously written with [`save`](IrIndex::save).
+    ///
+    /// The file must not be modified while the index is alive.
+    ///
+    /// Returns an error if the file is not a valid index, if it was written by an incompatible
+    /// version, or if the platform is not 64-bit little-endian.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        if !cfg!(all(target_pointer_width = "64", target_endian = "little")) {
+            bail!("memory mapped indexes are only supported on 64-bit little-endian platforms.")
+        }
+
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        // SAFETY: the documentation requires the file not to be modified while mapped.
+        let mmap = Arc::new(unsafe { Mmap::map(&file)? });
+
+        let header_len = INDEX_MAGIC.len() + 3 * WORD;
+        if mmap.len() < header_len || &mmap[..INDEX_MAGIC.len()] != INDEX_MAGIC {
+            bail!("'{}' is not an iirs index.", path.display())
+        }
+        let read_word = |idx: usize| {
+            let start = INDEX_MAGIC.len() + idx * WORD;
+            let bytes: [u8; WORD] = mmap[start..start + WORD].try_into().unwrap();
+            u64::from_le_bytes(bytes) as usize
+        };
+        let (version, n, lgn) = (read_word(0), read_word(1), read_word(2));
+        if version != INDEX_VERSION {
+            bail!(
+                "'{}' has index version {version} but version {INDEX_VERSION} is required. Rebuild the index.",
+                path.display()
+            )
+        }
+
+        // The layout of the header, checked for overflows: a corrupted one can have any value
+        let layout = || {
+            let s_n = n.checked_mul(2)?.checked_add(2)?;
+            let words = s_n.checked_mul(WORD)?;
+            let inv_sa_offset = header_len.checked_add(s_n)?.checked_add(padding(s_n))?;
+            let lcp_offset = inv_sa_offset.checked_add(words)?;
+            let table_offset = lcp_offset.checked_add(words)?;
+            let expected_len = table_offset.checked_add(words.checked_mul(lgn)?)?;
+            Some((s_n, expected_len))
+        };
+        let layout = layout().filter(|&(s_n, ..)| lgn == s_n.ilog2() as usize + 1);
+        let Some((s_n, expected_len)) = layout else {
+            bail!(
+                "'{}' is truncated or corrupted: invalid header (n={n}, lgn={lgn}).",
+                path.display()
+            )
+        };
+        if mmap.len() != expected_len {
+            bail!(
+                "'{}' is truncated or corrupted: expected {expected_len} bytes, found {}.",
+                path.display(),
+                mmap.len()
+            )
+        }
+        // They do not overflow, as they are less than the expected length
+        let s_offset = header_len;
+        let inv_sa_offset = s_offset + s_n + padding(s_n);
+        let lcp_offset = inv_sa_offset + s_n * WORD;
+
+        Ok(Self {
+            s: Buf::mapped(&mmap, s_offset, s_n),
+            mirror: false,
+            inv_sa: Buf::mapped(&mmap, inv_sa_offset, s_n),
+            #[cfg(not(feature = "tabulation"))]
+            rmq: SparseTable {
+                lgn,
+                table: Buf::mapped(&mmap, lcp_offset + s_n * WORD, s_n * lgn),
+            },
+            #[cfg(feature = "tabulation")]
+            lcp: Buf::mapped(&mmap, lcp_offset, s_n),
+        })
+    }
+}
+
+/// Deal with the sorting strategy.
+///
+/// Alternatives, or even skipping sorting altogether, can improve the performance.
+/// The original IUPACpal sorts by (left, gap_size, -right)
+pub(crate) fn sort_irs(irs: &mut [InvertedRepeat]) {
+    irs.sort_by(|a, b| {
+        let cmp_left = a.left.cmp(&b.left);
+        let cmp_gap = a.gap.cmp(&a.gap);
+        let cmp_right = b.right.cmp(&a.right);
+        cmp_left.then(cmp_gap).then(cmp_right)
+    });
+}
+
+/// Number of centers searched at once by [`IrIndex::search_with`].
+const CENTERS_PER_BATCH: usize = 1 << 16;
+
+/// Passes the IRs, found in batches of increasing centers, to a callback in the order
+/// of [`sort_irs`].
+///
+/// An IR spans at most `span = 2 * max_len + max_gap` positions, so the ones centered at
+/// `c` or after have `left >= (c - span) / 2`. Once all the centers before `c` have been
+/// searched, the pending IRs to the left of that are final.
+pub(crate) struct OrderedIrs<F> {
+    pending: Vec<InvertedRepeat>,
+    span: usize,
+    f: F,
+}
+
+impl<F: FnMut(InvertedRepeat) -> Result<()>> OrderedIrs<F> {
+    pub(crate) fn new(params: &SearchParams, f: F) -> Self {
+        Self {
+            pending: Vec::new(),
+            span: params
+                .max_len
+                .saturating_mul(2)
+                .saturating_add(params.max_gap),
+            f,
+        }
+    }
+
+    /// Add the IRs centered before `next_center` that were not yet pushed.
+    ///
+    /// They are expected in order of increasing center, as returned by the search.
+    pub(crate) fn push(&mut self, irs: Vec<InvertedRepeat>, next_center: usize) -> Result<()> {
+        self.pending.extend(irs);
+        // Stable, so that IRs with the same (left, right) keep the order of the search.
+        sort_irs(&mut self.pending);
+
+        let bound = next_center.saturating_sub(self.span) / 2;
+        let done = self.pending.partition_point(|ir| ir.left < bound);
+        self.pending.drain(..done).try_for_each(&mut self.f)
+    }
+
+    pub(crate) fn finish(mut self) -> Result<()> {
+        sort_irs(&mut self.pending);
+        self.pending.drain(..).try_for_each(&mut self.f)
+    }
+}
+
+/// Suffix array with 64-bit indices, for texts that do not fit in the `i32` of divsufsort.
+fn suffix_array_64(s: &[u8]) -> Result<Vec<i64>> {
+    let sa = libsais::SuffixArrayConstruction::for_text(s)
+        .in_owned_buffer64()
+        .single_threaded()
+        .run()
+        .map_err(|err| anyhow!("suffix array construction failed: {err}"))?;
+    Ok(sa.into_vec())
+}
+
+fn inv_sa_and_lcp<T: SaIndex>(s: &[u8], sa: &[T]) -> (Vec<usize>, Vec<usize>) {
+    let mut inv_sa = vec![0; s.len()];
+    for (i, value) in sa.iter().enumerate() {
+        inv_sa[value.as_usize()] = i;
+    }
+    let lcp = algo::lcp_array(s, s.len(), sa, &inv_sa);
+    (inv_sa, lcp)
+}
+
+const INDEX_MAGIC: &[u8; 8] = b"IIRSIDX\0";
+/// Increase when the layout of the index file changes.
+const INDEX_VERSION: usize = 1;
+const WORD: usize = std::mem::size_of::<u64>();
+
+/// Padding needed after `len` bytes to be aligned to a `u64`.
+const fn padding(len: usize) -> usize {
+    (WORD - len % WORD) % WORD
+}
+
+/// Marker for the types that can be read from a memory mapped index file.
+///
+/// # Safety
+///
+/// Any bit pattern of the right size must be a valid value of the type.
+unsafe trait Plain: Copy {}
+unsafe impl Plain for u8 {}
+unsafe impl Plain for usize {}
+
+/// A slice that is either owned or read from a memory mapped index file.
+enum Buf<T: Plain> {
+    Owned(Vec<T>),
+    Mapped {
+        mmap: Arc<Mmap>,
+        /// In bytes. It must be aligned for `T` (see [`IrIndex::load`]).
+        offset: usize,
+        /// In number of `T`.
+        len: usize,
+    },
+}
+
+impl<T: Plain> Buf<T> {
+    fn mapped(mmap: &Arc<Mmap>, offset: usize, len: usize) -> Self {
+        Self::Mapped {
+            mmap: Arc::clone(mmap),
+            offset,
+            len,
+        }
+    }
+}
+
+impl<T: Plain> Deref for Buf<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Self::Owned(values) => values,
+            Self::Mapped { mmap, offset, len } => {
+                let bytes = &mmap[*offset..*offset + len * std::mem::size_of::<T>()];
+                debug_assert!(bytes.as_ptr().cast::<T>().is_aligned());
+                // SAFETY: the bytes are in bounds and aligned (mmaps are page aligned and every
+                // section of the file is padded), and any bit pattern is a valid `T`.
+                unsafe { std::slice::from_raw_parts(bytes.as_ptr().cast::<T>(), *len) }
+            }
+        }
+    }
+}
+
+/// Sparse Table for the RMQ.
+///
+/// Same layout as `rmq::Sparse`, but the table can be persisted and memory mapped.
+struct SparseTable {
+    lgn: usize,
+    table: Buf<usize>,
+}
+
+impl SparseTable {
+    fn new(array: &[usize]) -> Self {
+        let n = array.len();
+        let lgn = n.ilog2() as usize + 1;
+        let mut table: Vec<usize> = vec![0; lgn * n];
+
+        for j in 0..n {
+            table[j * lgn] = array[j];
+        }
+
+        for i in 1..lgn {
+            for j in 0..=(n - (1 << i)) {
+                table[j * lgn + i] = std::cmp::min(
+                    table[j * lgn + i - 1],
+                    table[(j + (1 << (i - 1))) * lgn + i - 1],
+                );
+            }
+        }
+
+        Self {
+            lgn,
+            table: Buf::Owned(table),
+        }
+    }
+
+    /// The array the table was built from (its first column).
+    #[cfg(not(feature = "tabulation"))]
+    fn base(&self) -> Vec<usize> {
+        self.table.iter().step_by(self.lgn).copied().collect()
+    }
+}
+
+impl rmq::Rmq for SparseTable {
+    #[inline]
+    fn rmq(&self, i: usize, j: usize) -> Option<usize> {
+        if i >= j {
+            return None;
+        }
+
+        let k = (j - i).ilog2() as usize;
+        Some(std::cmp::min(
+            self.table[i * self.lgn + k],
+            self.table[(j - (1 << k)) * self.lgn + k],
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_reuse() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".as_bytes();
+        let index = IrIndex::build(seq).unwrap();
+        for (min_len, max_gap, mismatches) in [(10, 5, 1), (10, 5, 0), (10, 0, 5), (2, 5, 0)] {
+            let params = SearchParams::new(min_len, 100, max_gap, mismatches).unwrap();
+            assert_eq!(
+                index.search(&params).unwrap(),
+                crate::find_irs(&params, seq).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_index_kinds() {
+        // The mirror repeats are searched without this index
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(3);
+        let index = IrIndex::build(seq.as_bytes()).unwrap();
+        for kind in [RepeatKind::Direct, RepeatKind::Mirror] {
+            let mut params = SearchParams::new(4, 20, 5, 1).unwrap();
+            params.kind = kind;
+            let irs = index.search(&params).unwrap();
+            assert!(!irs.is_empty());
+            assert_eq!(irs, crate::find_irs(&params, seq.as_bytes()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_index_search_with() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(2000);
+        let index = IrIndex::build(seq.as_bytes()).unwrap();
+        for (min_len, max_len, max_gap, mismatches) in [(10, 100, 5, 1), (3, 5, 9, 2)] {
+            let params = SearchParams::new(min_len, max_len, max_gap, mismatches).unwrap();
+            let mut irs = Vec::new();
+            index
+                .search_with(&params, |ir| {
+                    irs.push(ir);
+                    Ok(())
+                })
+                .unwrap();
+            assert_eq!(irs, index.search(&params).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_index_search_with_error() {
+        let index = IrIndex::build(b"acbbgtttacbbgt").unwrap();
+        let params = SearchParams::new(3, 6, 2, 0).unwrap();
+        let mut calls = 0;
+        let result = index.search_with(&params, |_| {
+            calls += 1;
+            bail!("stop")
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_suffix_array_64() {
+        let seq = b"agucsgtwgtgtgtwkmmmkkbddn-nn*hagttwguvvvnnaguguta$tacacu#";
+        let sa_32 = divsufsort::sort(seq).into_parts().1;
+        let sa_64 = suffix_array_64(seq).unwrap();
+        assert!(
+            sa_32
+                .iter()
+                .map(|&x| i64::from(x))
+                .eq(sa_64.iter().copied())
+        );
+        assert_eq!(inv_sa_and_lcp(seq, &sa_32), inv_sa_and_lcp(seq, &sa_64));
+    }
+
+    #[test]
+    fn test_index_invalid_sequence() {
+        assert!(IrIndex::build(b"jj").is_err());
+    }
+
+    #[test]
+    fn test_index_save_load() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".as_bytes();
+        let path = std::env::temp_dir().join("iirs_test_index_save_load.idx");
+        let index = IrIndex::build(seq).unwrap();
+        index.save(&path).unwrap();
+        let loaded = IrIndex::load(&path).unwrap();
+        assert_eq!(loaded.seq(), index.seq());
+
+        let params = SearchParams::new(10, 100, 5, 1).unwrap();
+        assert_eq!(
+            loaded.search(&params).unwrap(),
+            index.search(&params).unwrap()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_index_load_invalid() {
+        let path = std::env::temp_dir().join("iirs_test_index_load_invalid.idx");
+        std::fs::write(&path, b"not an index").unwrap();
+        assert!(IrIndex::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_index_load_corrupted_header() {
+        let path = std::env::temp_dir().join("iirs_test_index_load_corrupted_header.idx");
+        let header_len = INDEX_MAGIC.len() + 3 * WORD;
+        let with_header = |n: u64, lgn: u64, len: usize| {
+            let mut bytes = INDEX_MAGIC.to_vec();
+            for value in [INDEX_VERSION as u64, n, lgn] {
+                bytes.extend(value.to_le_bytes());
+            }
+            bytes.resize(len.max(header_len), 0);
+            std::fs::write(&path, bytes).unwrap();
+            IrIndex::load(&path)
+        };
+
+        // The length matches the header, but there is no sparse table
+        let (n, s_n) = (5, 12);
+        let len = header_len + s_n + padding(s_n) + 2 * s_n * WORD;
+        let err = with_header(n, 0, len).err().unwrap();
+        assert!(err.to_string().contains("truncated or corrupted"), "{err}");
+        // A wrong number of columns, with the matching length
+        let lgn = s_n.ilog2() as usize + 2;
+        assert!(with_header(n, lgn as u64, len + s_n * lgn * WORD).is_err());
+        // The layout overflows
+        assert!(with_header(u64::MAX / 2, 64, 0).is_err());
+        assert!(with_header(1 << 60, 62, 0).is_err());
+
+        let lgn = s_n.ilog2() as usize + 1;
+        assert!(with_header(n, lgn as u64, len + s_n * lgn * WORD).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
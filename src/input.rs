@@ -0,0 +1,594 @@
+use anyhow::{Context, Result, anyhow, bail};
+use flate2::read::MultiGzDecoder;
+use seq_io::{fasta, fastq};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::ops::Range;
+
+use crate::flatfile::{self, Feature, FlatFile};
+use crate::ir::InvertedRepeat;
+use crate::mask::SoftMask;
+use crate::utils::{
+    InvalidPolicy, ValidationReport, check_file_exist, sanitize_or_report, sanitize_replacing,
+};
+
+/// Input path that reads from the standard input.
+pub const STDIN_PATH: &str = "-";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Options on how to read the input sequences.
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    /// FASTQ only: replace by `n` the bases with a (Phred+33) quality below this one.
+    /// By default, the qualities are ignored.
+    pub min_quality: Option<u8>,
+    /// What to do with the sequences that contain characters that are not IUPAC symbols.
+    /// By default, reading fails.
+    pub on_invalid: InvalidPolicy,
+}
+
+/// A sequence of the input file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqRecord {
+    /// The id of the sequence: the first word of its header, or the accession for GenBank
+    /// and EMBL.
+    pub id: String,
+    /// The sanitized sequence (see [`crate::find_irs`]).
+    pub seq: Vec<u8>,
+    /// GenBank / EMBL only: whether the topology of the molecule is circular.
+    pub circular: bool,
+    /// GenBank / EMBL only: the feature annotations, sorted by start.
+    pub features: Vec<Feature>,
+    /// The positions in lowercase in the input file, if the sequence mixes both cases.
+    pub soft_mask: Option<SoftMask>,
+}
+
+impl SeqRecord {
+    /// The features overlapping the span of `ir` (from its left arm to its right arm).
+    ///
+    /// If the record is circular, the part of the IR past the end of the sequence wraps
+    /// around to its start.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use iirs::{SearchParams, find_irs, read_records};
+    ///
+    /// let params = SearchParams::default();
+    /// for record in read_records("plasmid.gb").unwrap() {
+    ///     for ir in find_irs(&params, &record.seq).unwrap() {
+    ///         let genes: Vec<_> = record.overlapping_features(&ir).map(|f| f.name()).collect();
+    ///         println!("{} {} {:?}", ir.left, ir.right, genes);
+    ///     }
+    /// }
+    /// ```
+    pub fn overlapping_features<'a>(
+        &'a self,
+        ir: &InvertedRepeat,
+    ) -> impl Iterator<Item = &'a Feature> + use<'a> {
+        let circular_len = self.circular.then_some(self.seq.len());
+        overlapping_features(&self.features, ir, circular_len)
+    }
+}
+
+/// The features (sorted by start) overlapping the span of `ir`, wrapping around
+/// `circular_len` if it is given (as the features can).
+pub(crate) fn overlapping_features<'a>(
+    features: &'a [Feature],
+    ir: &InvertedRepeat,
+    circular_len: Option<usize>,
+) -> impl Iterator<Item = &'a Feature> + use<'a> {
+    let span = ir.left..ir.right + 1;
+    let (wrapped, shifted, candidates) = match circular_len {
+        // The features wrapping around the origin start after the IR, and end past it
+        Some(n) => (
+            0..span.end.saturating_sub(n),
+            span.start + n..span.end + n,
+            features.len(),
+        ),
+        // Only the features starting before the end of the IR can overlap it
+        None => (
+            0..0,
+            0..0,
+            features.partition_point(|feature| feature.start < span.end),
+        ),
+    };
+    features[..candidates].iter().filter(move |feature| {
+        feature.overlaps(&span) || feature.overlaps(&wrapped) || feature.overlaps(&shifted)
+    })
+}
+
+/// Read all the sequences of a FASTA, FASTQ, GenBank or EMBL file.
+///
+/// The format is detected from the start of the file (`@` for FASTQ, `LOCUS` for GenBank
+/// and `ID` for EMBL). The file can be compressed
+/// with gzip, bgzip or zstd, which is detected from its first bytes, and `-` reads from
+/// the standard input.
+///
+/// Returns an error if the file can not be read, or some sequence contains invalid characters.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use iirs::{SearchParams, find_irs, read_records};
+///
+/// let params = SearchParams::default();
+/// for record in read_records("genome.fna.gz").unwrap() {
+///     let irs = find_irs(&params, &record.seq).unwrap();
+///     println!("{}: {} IRs", record.id, irs.len());
+/// }
+/// ```
+pub fn read_records(path: &str) -> Result<Vec<SeqRecord>> {
+    read_records_with(path, &ReadOptions::default())
+}
+
+/// Same as [`read_records`], with the given [`ReadOptions`].
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use iirs::{ReadOptions, read_records_with};
+///
+/// // Mask the bases with a quality below 20
+/// let options = ReadOptions {
+///     min_quality: Some(20),
+///     ..Default::default()
+/// };
+/// let records = read_records_with("reads.fastq.gz", &options).unwrap();
+/// ```
+pub fn read_records_with(path: &str, options: &ReadOptions) -> Result<Vec<SeqRecord>> {
+    safe_extract_records(path, &[String::from("ALL_SEQUENCES")], options)
+}
+
+/// Open `path` for reading (`-` for the standard input), decompressing it if needed.
+pub fn open(path: &str) -> Result<Box<dyn Read>> {
+    if path == STDIN_PATH {
+        return decompress(io::stdin().lock());
+    }
+
+    check_file_exist(path)?;
+    decompress(File::open(path)?)
+}
+
+/// Wrap `inner` in a decoder if it starts with the magic bytes of gzip (which includes
+/// bgzip, a series of gzip members) or zstd.
+fn decompress<R: Read + 'static>(inner: R) -> Result<Box<dyn Read>> {
+    let (magic, reader) = peek(inner, ZSTD_MAGIC.len())?;
+
+    Ok(if magic.starts_with(&GZIP_MAGIC) {
+        Box::new(MultiGzDecoder::new(reader))
+    } else if magic.starts_with(&ZSTD_MAGIC) {
+        Box::new(zstd::Decoder::new(reader)?)
+    } else {
+        Box::new(reader)
+    })
+}
+
+/// Read the first `n` bytes of `inner` (less if it is shorter), and return them together
+/// with a reader that yields them again before the rest.
+fn peek<R: Read>(mut inner: R, n: usize) -> io::Result<(Vec<u8>, impl Read)> {
+    let mut head = Vec::with_capacity(n);
+    (&mut inner).take(n as u64).read_to_end(&mut head)?;
+    Ok((head.clone(), Cursor::new(head).chain(inner)))
+}
+
+/// Attempts to extract the record of every sequence with id in `seq_ids` from the input file.
+///
+/// If `seq_ids` is only `ALL_SEQUENCES` then all the sequences are extracted.
+/// For example:
+///
+/// `iirs -s ALL_SEQUENCES -m 5`
+///
+/// If at least one sequence is not found, returns an error with the list of missing
+/// sequences, together with a list of all the sequences present in the input file.
+pub fn safe_extract_records(
+    input_file: &str,
+    seq_ids: &[String],
+    options: &ReadOptions,
+) -> Result<Vec<SeqRecord>> {
+    Ok(extract_records(input_file, seq_ids, options)?.records)
+}
+
+/// The records extracted by [`extract_records`].
+pub struct ExtractedRecords {
+    pub records: Vec<SeqRecord>,
+    /// The ids and reports of the records with invalid characters, that were skipped or
+    /// repaired according to [`ReadOptions::on_invalid`].
+    pub invalid: Vec<(String, ValidationReport)>,
+}
+
+/// Same as [`safe_extract_records`], also returning the reports of the records with
+/// invalid characters.
+pub fn extract_records(
+    input_file: &str,
+    seq_ids: &[String],
+    options: &ReadOptions,
+) -> Result<ExtractedRecords> {
+    let do_all_sequences = seq_ids == ["ALL_SEQUENCES"];
+
+    let mut all_seq_ids_found = Vec::new();
+    let mut seq_ids_not_found = seq_ids.to_vec();
+    let mut records = Vec::new();
+    let mut invalid = Vec::new();
+
+    let mut add_record =
+        |record_id: &str, seq: &[u8], circular: bool, features: Vec<Feature>| -> Result<()> {
+            if do_all_sequences || seq_ids.iter().any(|id| id == record_id) {
+                seq_ids_not_found.retain(|id| id != record_id);
+                let raw_seq = seq;
+                let seq = match sanitize_or_report(seq) {
+                    Ok(seq) => seq,
+                    Err(report) => match options.on_invalid {
+                        InvalidPolicy::Error => bail!("Sequence '{record_id}' {report}."),
+                        InvalidPolicy::SkipRecord => {
+                            invalid.push((record_id.to_string(), report));
+                            all_seq_ids_found.push(record_id.to_string());
+                            return Ok(());
+                        }
+                        InvalidPolicy::Replace(replacement) => {
+                            invalid.push((record_id.to_string(), report));
+                            sanitize_replacing(seq, replacement)
+                        }
+                    },
+                };
+                records.push(SeqRecord {
+                    id: record_id.to_string(),
+                    soft_mask: SoftMask::from_seq(raw_seq),
+                    seq,
+                    circular,
+                    features,
+                });
+            }
+
+            all_seq_ids_found.push(record_id.to_string());
+            Ok(())
+        };
+
+    let (head, input) = peek(open(input_file)?, FlatFile::DETECT_LEN)?;
+    let input = BufReader::new(input);
+    if let Some(format) = FlatFile::detect(&head) {
+        flatfile::parse(input, format, |record| {
+            add_record(&record.id, &record.seq, record.circular, record.features)
+        })?;
+    } else if head.first() == Some(&b'@') {
+        use fastq::Record;
+        let mut reader = fastq::Reader::new(input);
+        while let Some(record) = reader.next() {
+            let record = record?;
+            match options.min_quality {
+                Some(min_quality) => {
+                    let seq = mask_low_quality(record.seq(), record.qual(), min_quality);
+                    add_record(record.id()?, &seq, false, Vec::new())?;
+                }
+                None => add_record(record.id()?, record.seq(), false, Vec::new())?,
+            }
+        }
+    } else {
+        use fasta::Record;
+        let mut reader = fasta::Reader::new(input);
+        while let Some(record) = reader.next() {
+            let record = record?;
+            add_record(record.id()?, record.seq(), false, Vec::new())?;
+        }
+    }
+
+    if !seq_ids_not_found.is_empty() && !do_all_sequences {
+        return Err(anyhow!(
+            "Sequence(s) '{}' not found.\nFound sequences in '{}' are:\n - {}",
+            seq_ids_not_found.join(", "),
+            input_file,
+            all_seq_ids_found.join("\n - ")
+        ));
+    }
+
+    Ok(ExtractedRecords { records, invalid })
+}
+
+/// Read the regions of a BED file (possibly compressed), grouped by sequence name.
+///
+/// Only the first three columns are used: the 0-based, half-open intervals. The header
+/// lines (`#`, `track` and `browser`) are skipped. The regions of every sequence are
+/// sorted, and the overlapping or adjacent ones merged.
+pub(crate) fn read_bed_regions(path: &str) -> Result<HashMap<String, Vec<Range<usize>>>> {
+    let mut regions: HashMap<String, Vec<Range<usize>>> = HashMap::new();
+    for (nline, line) in BufReader::new(open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+
+        let fields: Vec<_> = line.split('\t').collect();
+        let [chrom, start, end, ..] = fields[..] else {
+            bail!(
+                "Line {} of BED file '{path}' has less than three columns.",
+                nline + 1
+            )
+        };
+        let parse = |field: &str| {
+            field.trim().parse::<usize>().with_context(|| {
+                format!(
+                    "Invalid position '{field}' at line {} of '{path}'.",
+                    nline + 1
+                )
+            })
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start >= end {
+            bail!(
+                "Empty region {start}..{end} at line {} of '{path}'.",
+                nline + 1
+            )
+        }
+        regions
+            .entry(chrom.to_string())
+            .or_default()
+            .push(start..end);
+    }
+
+    for ranges in regions.values_mut() {
+        *ranges = merge_regions(std::mem::take(ranges));
+    }
+    Ok(regions)
+}
+
+/// Sort the regions and merge the overlapping or adjacent ones.
+fn merge_regions(mut regions: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    regions.sort_unstable_by_key(|region| region.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(regions.len());
+    for region in regions {
+        match merged.last_mut() {
+            Some(last) if region.start <= last.end => last.end = last.end.max(region.end),
+            _ => merged.push(region),
+        }
+    }
+    merged
+}
+
+/// Replace by `n` the bases with a (Phred+33) quality below `min_quality`.
+fn mask_low_quality(seq: &[u8], qual: &[u8], min_quality: u8) -> Vec<u8> {
+    seq.iter()
+        .zip(qual)
+        .map(|(&base, &q)| {
+            if q.saturating_sub(b'!') < min_quality {
+                // Keep the case, which is the soft mask
+                if base.is_ascii_uppercase() {
+                    b'N'
+                } else {
+                    b'n'
+                }
+            } else {
+                base
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compression, write::GzEncoder};
+    use std::io::Write;
+
+    const FASTA: &[u8] = b">seq0 first\nACGT\nacgn\n>seq1\nuuaa\n";
+
+    fn read_all(data: Vec<u8>) -> Vec<u8> {
+        let mut out = Vec::new();
+        decompress(Cursor::new(data))
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decompress_plain() {
+        assert_eq!(read_all(FASTA.to_vec()), FASTA);
+        assert_eq!(read_all(b">".to_vec()), b">");
+        assert!(read_all(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_decompress_gzip() {
+        assert_eq!(read_all(gzip(FASTA)), FASTA);
+    }
+
+    #[test]
+    fn test_decompress_bgzip() {
+        // Blocked gzip is a concatenation of gzip members.
+        let (first, second) = FASTA.split_at(10);
+        let mut data = gzip(first);
+        data.extend(gzip(second));
+        assert_eq!(read_all(data), FASTA);
+    }
+
+    #[test]
+    fn test_decompress_zstd() {
+        let data = zstd::encode_all(FASTA, 0).unwrap();
+        assert_eq!(read_all(data), FASTA);
+    }
+
+    #[test]
+    fn test_read_records_gzip() {
+        let path = std::env::temp_dir().join("iirs_test_read_records.fasta.gz");
+        std::fs::write(&path, gzip(FASTA)).unwrap();
+        let records = read_records(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let expected = [
+            SeqRecord {
+                id: String::from("seq0"),
+                seq: b"acgtacgn".to_vec(),
+                circular: false,
+                features: Vec::new(),
+                soft_mask: SoftMask::from_seq(b"ACGTacgn"),
+            },
+            SeqRecord {
+                id: String::from("seq1"),
+                seq: b"uuaa".to_vec(),
+                circular: false,
+                features: Vec::new(),
+                soft_mask: None,
+            },
+        ];
+        assert_eq!(records, expected);
+    }
+
+    #[test]
+    fn test_read_records_fastq() {
+        let fastq = b"@read0 desc\nACGTAC\n+\nII#I5I\n@read1\nuuaa\n+read1\nIIII\n";
+        let path = std::env::temp_dir().join("iirs_test_read_records.fastq.zst");
+        std::fs::write(&path, zstd::encode_all(&fastq[..], 0).unwrap()).unwrap();
+        let path_str = path.to_str().unwrap();
+
+        let records = read_records(path_str).unwrap();
+        assert_eq!(records[0].id, "read0");
+        assert_eq!(records[0].seq, b"acgtac");
+        assert_eq!(records[1].seq, b"uuaa");
+
+        // '#' is a quality of 2 and '5' of 20
+        let options = ReadOptions {
+            min_quality: Some(20),
+            ..Default::default()
+        };
+        let records = read_records_with(path_str, &options).unwrap();
+        assert_eq!(records[0].seq, b"acntac");
+
+        let options = ReadOptions {
+            min_quality: Some(21),
+            ..Default::default()
+        };
+        let records = read_records_with(path_str, &options).unwrap();
+        assert_eq!(records[0].seq, b"acntnc");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_extract_records_genbank() {
+        let genbank = "\
+LOCUS       pTEST                     12 bp    DNA     circular SYN 01-JAN-2000
+ACCESSION   AB000001
+FEATURES             Location/Qualifiers
+     gene            3..5
+                     /gene=\"tst\"
+ORIGIN
+        1 acgtNNacgt ac
+//
+LOCUS       other                      4 bp    DNA     linear   SYN 01-JAN-2000
+ORIGIN
+        1 acgt
+//
+";
+        let path = std::env::temp_dir().join("iirs_test_extract_records.gb");
+        std::fs::write(&path, genbank).unwrap();
+        let seq_ids = [String::from("AB000001")];
+        let result =
+            safe_extract_records(path.to_str().unwrap(), &seq_ids, &ReadOptions::default());
+        std::fs::remove_file(&path).unwrap();
+
+        let records = result.unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.seq, b"acgtnnacgtac");
+        assert!(record.circular);
+
+        let ir = InvertedRepeat::new(4, 7, 0);
+        let names: Vec<_> = record
+            .overlapping_features(&ir)
+            .map(Feature::name)
+            .collect();
+        assert_eq!(names, ["tst"]);
+        let ir = InvertedRepeat::new(5, 7, 0);
+        assert_eq!(record.overlapping_features(&ir).count(), 0);
+    }
+
+    #[test]
+    fn test_extract_records_not_found() {
+        let path = std::env::temp_dir().join("iirs_test_extract_records_not_found.fasta");
+        std::fs::write(&path, FASTA).unwrap();
+        let seq_ids = [String::from("seq1"), String::from("seq2")];
+        let result =
+            safe_extract_records(path.to_str().unwrap(), &seq_ids, &ReadOptions::default());
+        std::fs::remove_file(&path).unwrap();
+
+        let err = result.unwrap_err().to_string();
+        assert!(err.starts_with("Sequence(s) 'seq2' not found."), "{err}");
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_read_bed_regions() {
+        let path = std::env::temp_dir().join("iirs_test_read_bed_regions.bed");
+        let bed = "track name=test\n# comment\nseq0\t10\t20\tname\nseq1\t0\t5\n\
+                   seq0\t0\t5\nseq0\t15\t30\nseq0\t30\t35\n";
+        std::fs::write(&path, bed).unwrap();
+        let regions = read_bed_regions(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions["seq0"], [0..5, 10..35]);
+        assert_eq!(regions["seq1"], [0..5]);
+    }
+
+    #[test]
+    fn test_read_bed_regions_invalid() {
+        let path = std::env::temp_dir().join("iirs_test_read_bed_regions_invalid.bed");
+        std::fs::write(&path, "seq0\t10\n").unwrap();
+        let result = read_bed_regions(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("less than three columns"), "{err}");
+    }
+
+    #[test]
+    fn test_extract_records_invalid() {
+        let path = std::env::temp_dir().join("iirs_test_extract_records_invalid.fasta");
+        std::fs::write(
+            &path,
+            b">ok\nacgt\n>typo\nacgt\nacjx\n>prot\nMKTAYIAKQRQISFVKSHF\n",
+        )
+        .unwrap();
+        let path_str = path.to_str().unwrap();
+        let all = [String::from("ALL_SEQUENCES")];
+
+        let err = safe_extract_records(path_str, &all, &ReadOptions::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Sequence 'typo' contains 'j' which is not an IUPAC symbol, at position 7 \
+            (2 invalid characters out of 8)."
+        );
+
+        let options = ReadOptions {
+            on_invalid: InvalidPolicy::SkipRecord,
+            ..Default::default()
+        };
+        let extracted = extract_records(path_str, &all, &options).unwrap();
+        let ids: Vec<_> = extracted.records.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, ["ok"]);
+        assert_eq!(extracted.invalid.len(), 2);
+        assert!(!extracted.invalid[0].1.looks_like_protein());
+        assert!(extracted.invalid[1].1.looks_like_protein());
+
+        let options = ReadOptions {
+            on_invalid: InvalidPolicy::Replace(b'n'),
+            ..Default::default()
+        };
+        let extracted = extract_records(path_str, &all, &options).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(extracted.records[1].seq, b"acgtacnn");
+        assert_eq!(extracted.invalid.len(), 2);
+    }
+}
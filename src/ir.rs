@@ -0,0 +1,298 @@
+use anyhow::{Result, bail};
+use std::ops::Range;
+use std::str::FromStr;
+
+/// An [Inverted Repeat](https://en.wikipedia.org/wiki/Inverted_repeat) (IR) found in a sequence.
+///
+/// All positions are 0-based and refer to the sanitized sequence. The IR spans `left..=right`:
+/// a left arm, a (possibly empty) gap or loop, and a right arm that is the reverse complement
+/// of the left arm, up to the allowed mismatches.
+///
+/// The arms have the same length, unless the IR was found allowing indels (see
+/// [`crate::SearchParams::max_edits`]): its [`ArmAlignment`] then tells how they pair.
+///
+/// ```text
+///  left                          right
+///   |                              |
+///   a c g t t   a a a   a a c g t
+///   \_______/   \___/   \_______/
+///   left arm     gap    right arm
+/// ```
+///
+/// The same struct holds the direct and mirror repeats (see [`RepeatKind`]), whose right arm
+/// is a copy or the reverse of the left arm instead.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InvertedRepeat {
+    /// Position of the first nucleotide of the left arm.
+    pub left: usize,
+    /// Position of the last nucleotide of the right arm.
+    pub right: usize,
+    /// Length of the gap (loop) between both arms.
+    pub gap: usize,
+    /// Positions in the left arm whose pairing with the right arm is a mismatch.
+    ///
+    /// Sorted in increasing order. The paired position in the right arm can be obtained
+    /// with [`InvertedRepeat::paired`].
+    pub mismatch_positions: Vec<usize>,
+    /// How the arms pair, if the IR was found allowing indels.
+    pub alignment: Option<ArmAlignment>,
+}
+
+impl InvertedRepeat {
+    pub const fn new(left: usize, right: usize, gap: usize) -> Self {
+        Self {
+            left,
+            right,
+            gap,
+            mismatch_positions: Vec::new(),
+            alignment: None,
+        }
+    }
+
+    /// Length of each of the two arms (of the left arm, if they differ because of indels).
+    pub const fn arm_len(&self) -> usize {
+        match &self.alignment {
+            Some(alignment) => alignment.left_len,
+            None => (self.right + 1 - self.left - self.gap) / 2,
+        }
+    }
+
+    /// Length of the right arm. It is the same as [`InvertedRepeat::arm_len`] without indels.
+    pub const fn right_arm_len(&self) -> usize {
+        self.right + 1 - self.left - self.gap - self.arm_len()
+    }
+
+    /// Number of edits between both arms: the mismatches, and the nucleotides of an arm
+    /// left unpaired by indels.
+    pub fn edits(&self) -> usize {
+        self.alignment
+            .as_ref()
+            .map_or(self.mismatches(), ArmAlignment::edits)
+    }
+
+    /// Number of mismatches between both arms.
+    pub const fn mismatches(&self) -> usize {
+        self.mismatch_positions.len()
+    }
+
+    /// Range of the left arm.
+    pub const fn left_arm(&self) -> Range<usize> {
+        self.left..self.left + self.arm_len()
+    }
+
+    /// Range of the right arm.
+    pub const fn right_arm(&self) -> Range<usize> {
+        self.right + 1 - self.right_arm_len()..self.right + 1
+    }
+
+    /// Range of the gap (loop) between both arms. It is empty if the gap is zero.
+    pub const fn gap_range(&self) -> Range<usize> {
+        self.left + self.arm_len()..self.right + 1 - self.right_arm_len()
+    }
+
+    /// Position in the right arm paired with position `pos` of the left arm (and vice versa).
+    ///
+    /// Only meaningful without indels: otherwise, see [`InvertedRepeat::alignment`]. For a
+    /// direct repeat, the arms are not reversed: `pos` pairs with `pos + right_arm().start - left`.
+    pub const fn paired(&self, pos: usize) -> usize {
+        self.left + self.right - pos
+    }
+
+    /// Slice of `seq` corresponding to the left arm.
+    pub fn left_arm_seq<'a>(&self, seq: &'a [u8]) -> &'a [u8] {
+        &seq[self.left_arm()]
+    }
+
+    /// Slice of `seq` corresponding to the right arm.
+    pub fn right_arm_seq<'a>(&self, seq: &'a [u8]) -> &'a [u8] {
+        &seq[self.right_arm()]
+    }
+
+    /// Slice of `seq` corresponding to the gap (loop).
+    pub fn gap_seq<'a>(&self, seq: &'a [u8]) -> &'a [u8] {
+        &seq[self.gap_range()]
+    }
+
+    /// Move the IR `offset` positions to the right.
+    pub(crate) fn shift(&mut self, offset: usize) {
+        self.left += offset;
+        self.right += offset;
+        for pos in &mut self.mismatch_positions {
+            *pos += offset;
+        }
+    }
+
+    /// Move the IR `offset` positions to the left.
+    pub(crate) fn unshift(&mut self, offset: usize) {
+        self.left -= offset;
+        self.right -= offset;
+        for pos in &mut self.mismatch_positions {
+            *pos -= offset;
+        }
+    }
+
+    /// The `(left, right, gap)` triple.
+    pub const fn as_tuple(&self) -> (usize, usize, usize) {
+        (self.left, self.right, self.gap)
+    }
+}
+
+/// The relation between the arms of the repeats to find.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RepeatKind {
+    /// The right arm is the reverse complement of the left arm: `acgt..acgt`.
+    #[default]
+    Inverted,
+    /// The right arm is a copy of the left arm, either right after it (tandem) or
+    /// separated by a spacer (dispersed): `aacg..aacg`.
+    Direct,
+    /// The right arm is the reverse of the left arm, without complement: `aacg..gcaa`.
+    Mirror,
+}
+
+impl RepeatKind {
+    /// Whether the right arm is read backwards, pairing the outer nucleotides first.
+    pub const fn is_reversed(self) -> bool {
+        !matches!(self, Self::Direct)
+    }
+
+    /// Whether a nucleotide pairs with the complement of the other one.
+    pub const fn is_complemented(self) -> bool {
+        matches!(self, Self::Inverted)
+    }
+}
+
+impl FromStr for RepeatKind {
+    type Err = anyhow::Error;
+
+    /// Parse `inverted`, `direct` or `mirror`.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "inverted" => Self::Inverted,
+            "direct" => Self::Direct,
+            "mirror" => Self::Mirror,
+            _ => bail!("'{s}' is not a repeat kind: expected inverted, direct or mirror."),
+        })
+    }
+}
+
+impl std::fmt::Display for RepeatKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmted = match self {
+            Self::Inverted => "inverted",
+            Self::Direct => "direct",
+            Self::Mirror => "mirror",
+        };
+        write!(f, "{fmted}")
+    }
+}
+
+/// How the arms of an IR found with indels pair, as a CIGAR-like list of operations.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ArmAlignment {
+    /// Length of the left arm.
+    pub left_len: usize,
+    /// One operation per column of the alignment of the left arm with the reversed right
+    /// arm, from the outer pair to the inner one: `=` (the nucleotides pair), `X` (they
+    /// are a mismatch), `I` (a nucleotide of the left arm is unpaired) or `D` (one of the
+    /// right arm is).
+    pub ops: Vec<u8>,
+}
+
+impl ArmAlignment {
+    /// Number of operations other than `=`.
+    pub fn edits(&self) -> usize {
+        self.ops.iter().filter(|&&op| op != b'=').count()
+    }
+
+    /// The operations with run-length encoding, as in a CIGAR string: `4=1I3=`.
+    pub fn cigar(&self) -> String {
+        let mut cigar = String::new();
+        for run in self.ops.chunk_by(|a, b| a == b) {
+            cigar.push_str(&run.len().to_string());
+            cigar.push(run[0] as char);
+        }
+        cigar
+    }
+
+    /// The positions of the nucleotides of each column, as `(left, right)`, for an IR
+    /// spanning `left..=right`. `None` is the side of an unpaired nucleotide.
+    pub fn columns(
+        &self,
+        left: usize,
+        right: usize,
+    ) -> impl Iterator<Item = (Option<usize>, Option<usize>)> + '_ {
+        let (mut l, mut r) = (left, right);
+        self.ops.iter().map(move |&op| {
+            let column = ((op != b'D').then_some(l), (op != b'I').then_some(r));
+            if op != b'D' {
+                l += 1;
+            }
+            if op != b'I' {
+                r = r.wrapping_sub(1);
+            }
+            column
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges() {
+        // acg ttaaa cgt
+        let seq = b"acgttaaacgt";
+        let ir = InvertedRepeat::new(0, 10, 5);
+        assert_eq!(ir.arm_len(), 3);
+        assert_eq!(ir.left_arm_seq(seq), b"acg");
+        assert_eq!(ir.gap_seq(seq), b"ttaaa");
+        assert_eq!(ir.right_arm_seq(seq), b"cgt");
+        assert_eq!(ir.paired(0), 10);
+        assert_eq!(ir.paired(2), 8);
+    }
+
+    #[test]
+    fn test_ranges_no_gap() {
+        let seq = b"xacgty";
+        let ir = InvertedRepeat::new(1, 4, 0);
+        assert_eq!(ir.arm_len(), 2);
+        assert_eq!(ir.left_arm_seq(seq), b"ac");
+        assert!(ir.gap_seq(seq).is_empty());
+        assert_eq!(ir.right_arm_seq(seq), b"gt");
+    }
+
+    #[test]
+    fn test_ranges_indel() {
+        // acgt aa acggt: the right arm has an extra g
+        let seq = b"acgtaaacggt";
+        let ir = InvertedRepeat {
+            alignment: Some(ArmAlignment {
+                left_len: 4,
+                ops: b"==D==".to_vec(),
+            }),
+            ..InvertedRepeat::new(0, 10, 2)
+        };
+        assert_eq!(ir.arm_len(), 4);
+        assert_eq!(ir.right_arm_len(), 5);
+        assert_eq!(ir.left_arm_seq(seq), b"acgt");
+        assert_eq!(ir.gap_seq(seq), b"aa");
+        assert_eq!(ir.right_arm_seq(seq), b"acggt");
+        assert_eq!(ir.edits(), 1);
+
+        let alignment = ir.alignment.as_ref().unwrap();
+        assert_eq!(alignment.cigar(), "2=1D2=");
+        let columns: Vec<_> = alignment.columns(ir.left, ir.right).collect();
+        assert_eq!(
+            columns,
+            [
+                (Some(0), Some(10)),
+                (Some(1), Some(9)),
+                (None, Some(8)),
+                (Some(2), Some(7)),
+                (Some(3), Some(6)),
+            ]
+        );
+    }
+}
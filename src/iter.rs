@@ -0,0 +1,223 @@
+use anyhow::{Result, bail};
+use std::borrow::Borrow;
+use std::ops::Range;
+
+use crate::algo::Reached;
+use crate::config::SearchParams;
+use crate::index::IrIndex;
+use crate::ir::InvertedRepeat;
+use crate::matrix::MatchMatrix;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Lazily find the [Inverted Repeats](https://en.wikipedia.org/wiki/Inverted_repeat) (IRs)
+/// in a sequence based on the provided parameters.
+///
+/// Unlike [`crate::find_irs`], the IRs are not sorted: they are yielded in order of
+/// increasing center (`left + right`), as the search produces them. Only the index is
+/// built upfront, so stopping early skips the rest of the search.
+///
+/// Returns an error if the given sequence contains invalid characters, or if `params` is
+/// circular (use [`crate::find_irs_with`] instead).
+///
+/// # Examples
+///
+/// ```rust
+/// use iirs::{SearchParams, find_irs_iter};
+///
+/// let seq = "acbbgtttacbbgt".as_bytes();
+/// let params = SearchParams::new(3, 6, 2, 0).unwrap();
+/// let first = find_irs_iter(&params, &seq).unwrap().next().unwrap();
+/// assert_eq!(first.as_tuple(), (0, 5, 0));
+///
+/// let no_gap = find_irs_iter(&params, &seq).unwrap().filter(|ir| ir.gap == 0).count();
+/// assert_eq!(no_gap, 2);
+/// ```
+pub fn find_irs_iter(params: &SearchParams, seq: &[u8]) -> Result<IrIter<IrIndex>> {
+    check_linear(params)?;
+    Ok(IrIter::new(IrIndex::build_for(seq, params)?, params))
+}
+
+/// Parallel and unordered version of [`find_irs_iter`].
+///
+/// The IRs of different centers are produced concurrently, so no order is guaranteed
+/// (unless they are collected, which keeps the order of [`find_irs_iter`]).
+///
+/// Returns an error if the given sequence contains invalid characters, or if `params` is
+/// circular.
+///
+/// # Examples
+///
+/// ```rust
+/// use iirs::{SearchParams, find_irs_par_iter};
+/// use rayon::prelude::*;
+///
+/// let seq = "acbbgtttacbbgt".as_bytes();
+/// let params = SearchParams::new(3, 6, 2, 0).unwrap();
+/// let no_gap = find_irs_par_iter(&params, &seq).unwrap().filter(|ir| ir.gap == 0).count();
+/// assert_eq!(no_gap, 2);
+/// ```
+#[cfg(feature = "parallel")]
+pub fn find_irs_par_iter(
+    params: &SearchParams,
+    seq: &[u8],
+) -> Result<impl ParallelIterator<Item = InvertedRepeat> + use<>> {
+    check_linear(params)?;
+    Ok(par_iter(IrIndex::build_for(seq, params)?, params))
+}
+
+fn check_linear(params: &SearchParams) -> Result<()> {
+    if params.circular {
+        bail!("Circular sequences are not supported by the iterators.")
+    }
+    params.check_supported()
+}
+
+/// Iterator over the IRs of an [`IrIndex`], in order of increasing center.
+///
+/// Created by [`find_irs_iter`] (owning the index) or [`IrIndex::iter`] (borrowing it).
+pub struct IrIter<I: Borrow<IrIndex>> {
+    index: I,
+    params: SearchParams,
+    matrix: MatchMatrix,
+    /// Centers not searched yet.
+    centers: Range<usize>,
+    step: usize,
+    /// IRs of the last searched centers not yielded yet.
+    pending: std::vec::IntoIter<InvertedRepeat>,
+}
+
+impl<I: Borrow<IrIndex>> IrIter<I> {
+    pub(crate) fn new(index: I, params: &SearchParams) -> Self {
+        let s_n = 2 * index.borrow().seq().len() + 2;
+        Self {
+            index,
+            params: params.clone(),
+            matrix: params.matrix(),
+            centers: 0..s_n,
+            step: centers_per_step(s_n),
+            pending: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<I: Borrow<IrIndex>> Iterator for IrIter<I> {
+    type Item = InvertedRepeat;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(ir) = self.pending.next() {
+                return Some(ir);
+            }
+            if self.centers.is_empty() {
+                return None;
+            }
+
+            let end = self.centers.end.min(self.centers.start + self.step);
+            let centers = self.centers.start..end;
+            self.centers.start = end;
+            self.pending = self
+                .index
+                .borrow()
+                .search_centers_in(&self.params, &self.matrix, centers, &Reached::default())
+                .into_iter();
+        }
+    }
+}
+
+/// Parallel iterator over the IRs of an [`IrIndex`], see [`find_irs_par_iter`].
+#[cfg(feature = "parallel")]
+pub(crate) fn par_iter<I: Borrow<IrIndex> + Send + Sync>(
+    index: I,
+    params: &SearchParams,
+) -> impl ParallelIterator<Item = InvertedRepeat> + use<I> {
+    let params = params.clone();
+    let matrix = params.matrix();
+    let s_n = 2 * index.borrow().seq().len() + 2;
+    let step = centers_per_step(s_n);
+
+    (0..s_n.div_ceil(step))
+        .into_par_iter()
+        .flat_map_iter(move |k| {
+            let centers = k * step..s_n.min((k + 1) * step);
+            index
+                .borrow()
+                .search_centers_in(&params, &matrix, centers, &Reached::default())
+        })
+}
+
+/// Number of centers searched at once.
+///
+/// Sequentially, the search is lazy down to a single center. In parallel, the centers are
+/// searched in batches to have enough work to split. With the `tabulation` feature, the
+/// RMQ is rebuilt for every batch, so there are only a few of them.
+const fn centers_per_step(s_n: usize) -> usize {
+    const BATCH: usize = 1 << 16;
+    if cfg!(feature = "tabulation") {
+        let step = s_n / 16;
+        if step > BATCH { step } else { BATCH }
+    } else if cfg!(feature = "parallel") {
+        BATCH
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_irs;
+    use crate::index::sort_irs;
+    use crate::ir::RepeatKind;
+
+    #[test]
+    fn test_iter_same_as_find_irs() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(10);
+        let params = SearchParams::new(5, 100, 5, 1).unwrap();
+        let mut irs: Vec<_> = find_irs_iter(&params, seq.as_bytes()).unwrap().collect();
+
+        // Yielded in order of increasing center
+        assert!(
+            irs.windows(2)
+                .all(|w| w[0].left + w[0].right <= w[1].left + w[1].right)
+        );
+
+        sort_irs(&mut irs);
+        assert_eq!(irs, find_irs(&params, seq.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn test_iter_kinds() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(10);
+        for kind in [RepeatKind::Direct, RepeatKind::Mirror] {
+            let mut params = SearchParams::new(5, 20, 8, 1).unwrap();
+            params.kind = kind;
+            let mut irs: Vec<_> = find_irs_iter(&params, seq.as_bytes()).unwrap().collect();
+            assert!(
+                irs.windows(2)
+                    .all(|w| w[0].left + w[0].right <= w[1].left + w[1].right)
+            );
+            sort_irs(&mut irs);
+            assert_eq!(irs, find_irs(&params, seq.as_bytes()).unwrap(), "{kind}");
+        }
+    }
+
+    #[test]
+    fn test_iter_invalid_sequence() {
+        let params = SearchParams::default();
+        assert!(find_irs_iter(&params, b"jj").is_err());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_par_iter_same_as_iter() {
+        let seq = "AGUCSGTWGTGTGTWKMMMKKBDDN-NN*HAGTTWGuVVVNNAGuGTA".repeat(10);
+        let params = SearchParams::new(5, 100, 5, 1).unwrap();
+        let expected: Vec<_> = find_irs_iter(&params, seq.as_bytes()).unwrap().collect();
+        let received: Vec<_> = find_irs_par_iter(&params, seq.as_bytes())
+            .unwrap()
+            .collect();
+        assert_eq!(received, expected);
+    }
+}
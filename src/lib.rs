@@ -1,23 +1,68 @@
 mod cli;
-pub use cli::Cli;
+pub use cli::{Cli, Command, IndexArgs};
 
 mod config;
 pub use config::{Config, SearchParams};
 
 mod constants;
-pub use constants::OutputFormat;
+pub use constants::{OutputFormat, STDOUT_PATH};
+
+mod chunk;
+pub use chunk::{
+    find_irs_chunked, find_irs_chunked_with, find_irs_in_regions, find_irs_in_regions_with,
+};
+use chunk::{find_irs_whole, find_irs_whole_with};
+
+mod index;
+pub use index::IrIndex;
+
+mod flatfile;
+pub use flatfile::Feature;
+
+mod input;
+pub use input::{ReadOptions, SeqRecord, read_records, read_records_with};
+pub use utils::{InvalidPolicy, ValidationReport};
+
+mod ir;
+pub use ir::{ArmAlignment, InvertedRepeat, RepeatKind};
+
+mod iter;
+#[cfg(feature = "parallel")]
+pub use iter::find_irs_par_iter;
+pub use iter::{IrIter, find_irs_iter};
+
+mod mask;
+pub use mask::SoftMask;
+
+mod matrix;
+pub use matrix::{MatchMatrix, MatchMode, NPolicy};
+
+mod report;
+pub use report::ReportMode;
+
+mod score;
+pub use score::ScoreWeights;
 
 mod algo;
+mod align;
+mod direct;
 mod format;
-mod matrix;
 mod utils;
 
 use anyhow::Result;
+use std::io::{self, Write};
 
 /// Find all the [Inverted Repeats](https://en.wikipedia.org/wiki/Inverted_repeat) (IRs) in a sequence
 /// based on the provided parameters.
 ///
-/// Each IR is returned a tuple of three integers (usize): start position, end position, and gap size.
+/// Each IR is returned as an [`InvertedRepeat`], sorted by their left position.
+///
+/// To scan the same sequence with different parameters, prefer building an [`IrIndex`] once.
+///
+/// If `params.circular` is set, the sequence is treated as circular: the IRs wrapping
+/// around its origin are also found, with `left < seq.len() <= right`. Their positions
+/// (and slices) are those of the sequence followed by itself, so take them modulo the
+/// length. Every IR is reported once.
 ///
 /// # Examples
 ///
@@ -27,9 +72,12 @@ use anyhow::Result;
 /// let seq = "acbbgt".as_bytes();
 /// let params = SearchParams::new(3, 6, 2, 0).unwrap();
 /// assert!(params.check_bounds(seq.len()).is_ok());
-/// let irs = find_irs(&params, &seq);
+/// let irs = find_irs(&params, &seq).unwrap();
 /// // The only IR in the sequence is "acbbgt"
-/// assert_eq!(irs.unwrap(), vec![(0, 5, 0)]);
+/// assert_eq!(irs.len(), 1);
+/// assert_eq!(irs[0].as_tuple(), (0, 5, 0));
+/// assert_eq!(irs[0].left_arm_seq(&seq), b"acb");
+/// assert_eq!(irs[0].right_arm_seq(&seq), b"bgt");
 ///
 /// // Returns an error if the given sequence contains invalid characters
 /// let seq = "jj".as_bytes();
@@ -38,68 +86,72 @@ use anyhow::Result;
 ///
 /// // It is not case-sensitive and ignores newlines.
 /// let seq = "ACB\n\rBGT".as_bytes();
-/// let irs = find_irs(&params, &seq);
-/// assert_eq!(irs.unwrap(), vec![(0, 5, 0)]);
+/// let irs = find_irs(&params, &seq).unwrap();
+/// assert_eq!(irs[0].as_tuple(), (0, 5, 0));
+/// ```
+///
+/// ```rust
+/// use iirs::{SearchParams, find_irs};
+///
+/// // The IR "acgt" wraps around the origin
+/// let seq = "gtaaaaac".as_bytes();
+/// let mut params = SearchParams::new(2, 4, 0, 0).unwrap();
+/// assert!(find_irs(&params, &seq).unwrap().is_empty());
+/// params.circular = true;
+/// let irs = find_irs(&params, &seq).unwrap();
+/// assert_eq!(irs[0].as_tuple(), (6, 9, 0));
 /// ```
 #[elapsed_time::elapsed]
-pub fn find_irs(params: &SearchParams, seq: &[u8]) -> Result<Vec<(usize, usize, usize)>> {
-    // Removes newlines, cast to lowercase and checks that all the character are in IUPAC.
-    // This was already done through the CLI, but we need to do it again for the standalone version.
-    let sanitized_seq = utils::sanitize_sequence(seq)?;
-
-    // Build matchmatrix
-    let matrix = matrix::MatchMatrix::new();
-    let complement = constants::build_complement_array();
-
-    // Construct s = seq + '$' + complement(reverse(seq)) + '#'
-    let n = sanitized_seq.len();
-    let s_n = 2 * n + 2;
-    let mut s = vec![0u8; s_n];
-    for i in 0..n {
-        s[i] = sanitized_seq[i];
-        s[n + 1 + i] = complement[sanitized_seq[n - 1 - i] as usize] as u8;
-    }
-    s[n] = b'$';
-    s[2 * n + 1] = b'#';
-
-    // Construct Suffix Array (sa) & Inverse Suffix Array
-    let sa: Vec<i32> = divsufsort::sort(&s).into_parts().1;
-    let mut inv_sa = vec![0; s_n];
-    for (i, value) in sa.iter().enumerate() {
-        inv_sa[*value as usize] = i;
+pub fn find_irs(params: &SearchParams, seq: &[u8]) -> Result<Vec<InvertedRepeat>> {
+    if params.circular {
+        return find_irs_whole(params, seq);
     }
+    let index = IrIndex::build_for(seq, params)?;
+    index.search(params)
+}
 
-    // Calculate LCP & RMQ
-    let lcp = algo::lcp_array(&s, s_n, &sa, &inv_sa);
-    // By default use the Sparse Table implementation for the Rmq
-    #[cfg(not(feature = "tabulation"))]
-    let rmq = rmq::Sparse::new(&lcp);
-    #[cfg(feature = "tabulation")]
-    let rmq = rmq::Tabulation::new(&lcp);
-
-    // Calculate inverted repeats
-    let mut irs = algo::add_irs(&s, &inv_sa, &rmq, params, &matrix);
-
-    // Deal with the sorting strategy.
-    // Alternatives, or even skipping sorting altogether, can improve the performance.
-    // The original IUPACpal sorts by (left, gap_size, -right)
-    irs.sort_by(|a, b| {
-        let cmp_left = a.0.cmp(&b.0);
-        let cmp_gap = a.2.cmp(&a.2);
-        let cmp_right = b.1.cmp(&a.1);
-        cmp_left.then(cmp_gap).then(cmp_right)
-    });
-
-    Ok(irs)
+/// Same as [`find_irs`], but calling `f` on each IR (in the same order) instead of
+/// collecting them.
+///
+/// Only a bounded amount of IRs is held in memory at any time. Stops at the first error
+/// returned by `f`, or returns an error if the given sequence contains invalid characters.
+///
+/// # Examples
+///
+/// ```rust
+/// use iirs::{SearchParams, find_irs_with};
+///
+/// let seq = "acbbgt".as_bytes();
+/// let params = SearchParams::new(3, 6, 2, 0).unwrap();
+/// let mut count = 0;
+/// find_irs_with(&params, &seq, |ir| {
+///     assert_eq!(ir.as_tuple(), (0, 5, 0));
+///     count += 1;
+///     Ok(())
+/// }).unwrap();
+/// assert_eq!(count, 1);
+/// ```
+pub fn find_irs_with<F>(params: &SearchParams, seq: &[u8], f: F) -> Result<()>
+where
+    F: FnMut(InvertedRepeat) -> Result<()>,
+{
+    if params.circular {
+        return find_irs_whole_with(params, seq, f);
+    }
+    IrIndex::build_for(seq, params)?.search_with(params, f)
 }
 
 /// Stringify the given [Inverted Repeats](https://en.wikipedia.org/wiki/Inverted_repeat) (IRs)
 /// based on the specified output format in the configuration.
 ///
 /// An error is returned for an invalid output format.
-/// Valid formats are: classic (same as `IUPACpal`), csv and custom.
+/// Valid formats are: classic (same as `IUPACpal`), csv, custom, json, jsonl, bed, bed12 and gff3.
 ///
 /// If the specified format is valid, the function returns a tuple of Strings: (header, stringified irs).
+/// For json, the document is closed at the end of the IRs. For jsonl, bed and bed12, the header
+/// is empty, and for gff3 it includes the `##sequence-region` of the sequence.
+///
+/// The whole output is built in memory: to write it incrementally use [`IrWriter`].
 ///
 /// # Examples
 ///
@@ -139,24 +191,213 @@ pub fn find_irs(params: &SearchParams, seq: &[u8]) -> Result<Vec<(usize, usize,
 ///     Palindromes:";
 /// assert_eq!(header, expected);
 /// ```
-pub fn stringify_irs(
-    config: &Config,
-    irs: &[(usize, usize, usize)],
-    seq: &[u8],
-) -> (String, String) {
-    let matrix = matrix::MatchMatrix::new();
+pub fn stringify_irs(config: &Config, irs: &[InvertedRepeat], seq: &[u8]) -> (String, String) {
+    let matrix = config.params.matrix();
     let complement = constants::build_complement_array();
+    let sequence = format::Sequence::new(seq, &config.params);
+
+    let mut irs_str = Vec::new();
+    for (nth, ir) in irs.iter().enumerate() {
+        irs_str.extend_from_slice(format::fmt_separator(&config.output_format, nth).as_bytes());
+        let score = config
+            .score
+            .then(|| sequence.score(ir, &config.score_weights));
+        format::write_ir(
+            &mut irs_str,
+            config,
+            ir,
+            nth,
+            &sequence,
+            score,
+            &matrix,
+            &complement,
+        )
+        .expect("Writing to a Vec can not fail");
+    }
+    irs_str.extend_from_slice(format::fmt_footer(&config.output_format, irs.len()).as_bytes());
+
+    (
+        format::fmt_header(config, seq.len()),
+        String::from_utf8(irs_str).expect("The sanitized sequence is ASCII"),
+    )
+}
+
+/// Writes [Inverted Repeats](https://en.wikipedia.org/wiki/Inverted_repeat) (IRs) incrementally,
+/// based on the specified output format in the configuration.
+///
+/// The output is the same as the one of [`stringify_irs`] (header, newline, IRs and a final
+/// newline), but each IR is written as soon as it is received. Combined with
+/// [`find_irs_with`], memory usage does not depend on the size of the output.
+///
+/// The IRs skipped by the filters of the config ([`Config::max_masked_fraction`] and
+/// [`Config::min_score`]) are not written. With [`Config::sort_by_score`], the IRs are
+/// collected and only written by [`IrWriter::finish`], from the highest score to the lowest.
+///
+/// # Examples
+///
+/// ```rust
+/// use iirs::{Config, IrWriter, OutputFormat, SearchParams, find_irs_with};
+///
+/// let seq = "acbbgt".as_bytes();
+/// let config = Config {
+///     params: SearchParams::new(3, 6, 2, 0).unwrap(),
+///     output_format: OutputFormat::Csv,
+///     ..Default::default()
+/// };
+/// let mut writer = IrWriter::new(Vec::new(), &config, &seq).unwrap();
+/// find_irs_with(&config.params, &seq, |ir| Ok(writer.write(&ir)?)).unwrap();
+/// let out = writer.finish().unwrap();
+/// let expected = "\
+///     start_n,end_n,nucleotide,start_ir,end_ir,reverse_complement,matching\n\
+///     1,3,acb,6,4,tgb,111\n\n";
+/// assert_eq!(String::from_utf8(out).unwrap(), expected);
+/// ```
+pub struct IrWriter<'a, W: Write> {
+    out: W,
+    config: &'a Config<'a>,
+    seq: format::Sequence<'a>,
+    matrix: matrix::MatchMatrix,
+    complement: [u8; 128],
+    /// Number of IRs written so far.
+    written: usize,
+    /// Number of IRs written before, by the writers of the previous sequences.
+    previous: usize,
+    /// The IRs to write by score, with their score (see [`Config::sort_by_score`]).
+    by_score: Vec<(f64, InvertedRepeat)>,
+}
+
+impl<'a, W: Write> IrWriter<'a, W> {
+    /// Writes the header (if any) and returns the writer, ready for the IRs of `seq`.
+    pub fn new(out: W, config: &'a Config<'a>, seq: &'a [u8]) -> io::Result<Self> {
+        let header = format::fmt_header(config, seq.len());
+        Self::with_header(out, config, seq, &header, 0)
+    }
+
+    /// Same as [`IrWriter::new`], for a sequence written after others to the same output
+    /// (for example stdout), after `previous` IRs in total.
+    ///
+    /// The outputs are simply concatenated, except for gff3 where the `##gff-version`
+    /// directive is only written by the first sequence. The bed and gff3 IRs keep being
+    /// numbered from `previous`, so that their names are unique in the whole output.
+    pub fn new_following(
+        out: W,
+        config: &'a Config<'a>,
+        seq: &'a [u8],
+        previous: usize,
+    ) -> io::Result<Self> {
+        let header = format::fmt_following_header(config, seq.len());
+        Self::with_header(out, config, seq, &header, previous)
+    }
+
+    fn with_header(
+        mut out: W,
+        config: &'a Config<'a>,
+        seq: &'a [u8],
+        header: &str,
+        previous: usize,
+    ) -> io::Result<Self> {
+        if !header.is_empty() {
+            writeln!(out, "{header}")?;
+        }
+        Ok(Self {
+            out,
+            config,
+            seq: format::Sequence::new(seq, &config.params),
+            matrix: config.params.matrix(),
+            complement: constants::build_complement_array(),
+            written: 0,
+            previous,
+            by_score: Vec::new(),
+        })
+    }
+
+    /// Annotate the json outputs with the names of the features overlapping each IR (see
+    /// [`SeqRecord::overlapping_features`]). `features` must be sorted by start, as in a
+    /// [`SeqRecord`].
+    #[must_use]
+    pub fn with_features(mut self, features: &'a [Feature]) -> Self {
+        self.seq = self.seq.with_features(features);
+        self
+    }
+
+    /// The soft mask of the sequence, for the masked fraction of the csv and json outputs
+    /// (see [`Config::soft_mask`]) and for [`Config::max_masked_fraction`]. Without it, the
+    /// fraction is zero.
+    #[must_use]
+    pub fn with_soft_mask(mut self, soft_mask: Option<&'a SoftMask>) -> Self {
+        self.seq = self.seq.with_soft_mask(soft_mask);
+        self
+    }
+
+    /// Writes `ir`, unless the filters of the config skip it (see
+    /// [`Config::max_masked_fraction`] and [`Config::min_score`]). With
+    /// [`Config::sort_by_score`], it is kept to be written by [`IrWriter::finish`].
+    pub fn write(&mut self, ir: &InvertedRepeat) -> io::Result<()> {
+        let config = self.config;
+        if config
+            .max_masked_fraction
+            .is_some_and(|max| self.seq.masked_fraction(ir) > max)
+        {
+            return Ok(());
+        }
+        // Computed once, for the filter, the order and the output
+        let scored = config.score || config.min_score.is_some() || config.sort_by_score;
+        let score = scored.then(|| self.seq.score(ir, &config.score_weights));
+        if let (Some(min_score), Some(score)) = (config.min_score, score)
+            && score < min_score
+        {
+            return Ok(());
+        }
+        if config.sort_by_score
+            && let Some(score) = score
+        {
+            self.by_score.push((score, ir.clone()));
+            return Ok(());
+        }
+        self.write_scored(ir, score)
+    }
+
+    fn write_scored(&mut self, ir: &InvertedRepeat, score: Option<f64>) -> io::Result<()> {
+        let format = &self.config.output_format;
+        self.out
+            .write_all(format::fmt_separator(format, self.written).as_bytes())?;
+        format::write_ir(
+            &mut self.out,
+            self.config,
+            ir,
+            self.previous + self.written,
+            &self.seq,
+            score.filter(|_| self.config.score),
+            &self.matrix,
+            &self.complement,
+        )?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Number of IRs written so far, or kept to be written by score, the skipped ones
+    /// excluded.
+    pub const fn n_irs(&self) -> usize {
+        self.written + self.by_score.len()
+    }
 
-    match config.output_format {
-        OutputFormat::Classic => (
-            format::fmt_classic_header(config, seq.len()),
-            format::fmt_classic(irs, seq, &matrix, &complement),
-        ),
-        OutputFormat::Csv => (
-            format::fmt_csv_header(),
-            format::fmt_csv(irs, seq, &matrix, &complement),
-        ),
-        OutputFormat::Custom => (format::fmt_custom_header(), format::fmt_custom(irs, seq)),
+    /// Writes the IRs kept to be sorted by score (if any), closes the output (the final
+    /// newline, or the end of the json document), flushes and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        // Stable: the IRs with the same score stay in the order they were received
+        let mut by_score = std::mem::take(&mut self.by_score);
+        by_score.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        for (score, ir) in &by_score {
+            self.write_scored(ir, Some(*score))?;
+        }
+        let format = &self.config.output_format;
+        self.out
+            .write_all(format::fmt_footer(format, self.written).as_bytes())?;
+        if !format::is_line_based(format) {
+            self.out.write_all(b"\n")?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
     }
 }
 
@@ -1,11 +1,16 @@
 use anyhow::Result;
 use seq_io::fasta::{Reader, Record};
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 use super::config::{Config, SearchParams};
 use super::constants;
 use super::find_irs;
-use super::matrix;
+use super::matrix::{self, MatchMode, NPolicy};
 use super::utils;
+use super::{
+    InvertedRepeat, IrIndex, RepeatKind, ReportMode, find_irs_chunked, find_irs_iter, find_irs_with,
+};
 
 /// Attemps to extract the first sequence (string) from the fasta file. Returns a trimmed lowercase String.
 ///
@@ -43,8 +48,8 @@ fn correct_truncation_helper(config: &Config) {
     s[2 * n + 1] = b'#';
     let matrix = matrix::MatchMatrix::new();
 
-    for (left, right, _) in irs {
-        assert!(matrix.match_u8(s[left], complement[s[right] as usize]),);
+    for ir in irs {
+        assert!(matrix.match_u8(s[ir.left], complement[s[ir.right] as usize]),);
     }
 }
 
@@ -81,13 +86,52 @@ fn test_correct_truncation_three() {
 // Tests from local files
 //
 // Test generator
-fn find_irs_from_first_sequence(config: &Config) -> Vec<(usize, usize, usize)> {
+fn find_irs_from_first_sequence(config: &Config) -> Vec<InvertedRepeat> {
     let string = extract_first_sequence(config).unwrap();
     let seq = string.to_ascii_lowercase().as_bytes().to_vec();
     config.params.check_bounds(seq.len()).unwrap(); // BUT THE OUTPUT FORMAT MIGHT BE WRONG?
     find_irs(&config.params, &seq).unwrap()
 }
 
+// The mismatch positions computed from the Kangaroo method must agree with
+// a direct comparison of the arms.
+fn correct_mismatch_positions_helper(config: &Config) {
+    let string = extract_first_sequence(config).unwrap();
+    let seq = string.as_bytes();
+    let irs = find_irs(&config.params, seq).unwrap();
+    let complement = constants::build_complement_array();
+    let matrix = matrix::MatchMatrix::new();
+
+    for ir in irs {
+        let expected: Vec<_> = ir
+            .left_arm()
+            .filter(|&pos| !matrix.match_u8(seq[pos], complement[seq[ir.paired(pos)] as usize]))
+            .collect();
+        assert_eq!(ir.mismatch_positions, expected, "{ir:?}");
+        assert!(ir.mismatches() <= config.params.mismatches);
+    }
+}
+
+#[test]
+fn test_correct_mismatch_positions_one() {
+    let config = Config {
+        params: SearchParams::new(8, 100, 10, 6).unwrap(),
+        input_file: "tests/test_data/truncation_edge_case.fasta",
+        ..Default::default()
+    };
+    correct_mismatch_positions_helper(&config);
+}
+
+#[test]
+fn test_correct_mismatch_positions_two() {
+    let config = Config {
+        params: SearchParams::new(3, 12, 20, 2).unwrap(),
+        input_file: "tests/test_data/d00596.fasta",
+        ..Default::default()
+    };
+    correct_mismatch_positions_helper(&config);
+}
+
 #[test]
 fn test_irs_edge_gap() {
     // The original IUPACpal won't find this IR
@@ -202,3 +246,675 @@ fn test_test_1() {
 //     };
 //     assert_eq!(find_irs_from_first_sequence(&config).len(), 253_566);
 // }
+
+#[test]
+fn test_writer_following_gff3() {
+    let seqs: [&[u8]; 2] = [b"acgtacgtacgtacgtacgt", b"ttttaaaattttaaaa"];
+    let params = SearchParams::new(8, 100, 2, 0).unwrap();
+    let mut out = Vec::new();
+    let mut previous = 0;
+    for (nth, (seq_name, seq)) in ["a", "b"].into_iter().zip(seqs).enumerate() {
+        let config = Config {
+            seq_name,
+            params: params.clone(),
+            output_format: constants::OutputFormat::Gff3,
+            ..Default::default()
+        };
+        let mut writer = if nth == 0 {
+            super::IrWriter::new(&mut out, &config, seq).unwrap()
+        } else {
+            super::IrWriter::new_following(&mut out, &config, seq, previous).unwrap()
+        };
+        for ir in find_irs(&config.params, seq).unwrap() {
+            writer.write(&ir).unwrap();
+            previous += 1;
+        }
+        writer.finish().unwrap();
+    }
+
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.matches("##gff-version 3").count(), 1);
+    assert!(out.contains("\n##sequence-region b 1 16\nb\tiirs\tinverted_repeat\t1\t16\t"));
+    // The IDs are unique in the whole output
+    assert!(out.contains("ID=ir4;"), "{out}");
+}
+
+#[test]
+fn test_writer_max_masked_fraction() {
+    // The first IR is lowercase (soft-masked), the second uppercase
+    let raw = b"aaaaaaaattttttttcccccccccAAAAAAAATTTTTTTT";
+    let soft_mask = super::SoftMask::from_seq(raw).unwrap();
+    let seq = raw.to_ascii_lowercase();
+    let mut config = Config {
+        params: SearchParams::new(8, 100, 0, 0).unwrap(),
+        output_format: constants::OutputFormat::Csv,
+        soft_mask: true,
+        ..Default::default()
+    };
+    let irs = find_irs(&config.params, &seq).unwrap();
+    assert_eq!(irs.len(), 2);
+
+    let write_all = |config: &Config| {
+        let mut writer = super::IrWriter::new(Vec::new(), config, &seq)
+            .unwrap()
+            .with_soft_mask(Some(&soft_mask));
+        for ir in &irs {
+            writer.write(ir).unwrap();
+        }
+        let n_irs = writer.n_irs();
+        (n_irs, String::from_utf8(writer.finish().unwrap()).unwrap())
+    };
+    let (n_irs, out) = write_all(&config);
+    assert_eq!(n_irs, 2);
+    assert!(
+        out.contains(",1.000\n") && out.contains(",0.000\n"),
+        "{out}"
+    );
+
+    config.max_masked_fraction = Some(0.5);
+    let (n_irs, out) = write_all(&config);
+    assert_eq!(n_irs, 1);
+    assert!(
+        !out.contains(",1.000\n") && out.contains(",0.000\n"),
+        "{out}"
+    );
+}
+
+#[test]
+fn test_writer_score() {
+    // Arms of 8, 10 and 9 pairs: the IRs score their length
+    let seq = b"aaaaaaaattttttttcccccaaaaaaaaaattttttttttcccccaaaaaaaaattttttttt";
+    let mut config = Config {
+        params: SearchParams::new(8, 100, 0, 0).unwrap(),
+        output_format: constants::OutputFormat::Custom,
+        ..Default::default()
+    };
+    let irs = find_irs(&config.params, seq).unwrap();
+    assert_eq!(irs.len(), 3);
+
+    let write_all = |config: &Config| {
+        let mut writer = super::IrWriter::new(Vec::new(), config, seq).unwrap();
+        for ir in &irs {
+            writer.write(ir).unwrap();
+        }
+        let n_irs = writer.n_irs();
+        let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+        // Without the header and the final newline
+        let starts: Vec<_> = out
+            .lines()
+            .skip(1)
+            .filter(|line| !line.is_empty())
+            .map(|line| line.split(',').next().unwrap().to_owned())
+            .collect();
+        (n_irs, starts)
+    };
+    assert_eq!(
+        write_all(&config),
+        (3, vec!["1".into(), "22".into(), "47".into()])
+    );
+
+    config.min_score = Some(9.0);
+    assert_eq!(write_all(&config), (2, vec!["22".into(), "47".into()]));
+
+    config.sort_by_score = true;
+    assert_eq!(write_all(&config), (2, vec!["22".into(), "47".into()]));
+
+    config.min_score = None;
+    assert_eq!(
+        write_all(&config),
+        (3, vec!["22".into(), "47".into(), "1".into()])
+    );
+}
+
+/// Check that the arms of every IR pair as the match mode says, except at its mismatches,
+/// and that they contain no character that breaks them.
+fn assert_consistent_pairing(params: &SearchParams, seq: &[u8]) -> Vec<InvertedRepeat> {
+    let matrix = params.matrix();
+    let irs = find_irs(params, seq).unwrap();
+    for ir in &irs {
+        assert!(ir.mismatches() <= params.mismatches, "{ir:?}");
+        for pos in ir.left_arm().chain(ir.right_arm()) {
+            assert!(!matrix.breaks_u8(seq[pos], seq[pos]), "{ir:?}");
+        }
+        for pos in ir.left_arm() {
+            let pairs = matrix.pairs(seq[pos], seq[ir.paired(pos)]);
+            assert_eq!(pairs, !ir.mismatch_positions.contains(&pos), "{ir:?}");
+        }
+    }
+    irs
+}
+
+#[test]
+fn test_match_modes_consistent() {
+    let iupac = Config {
+        input_file: "tests/test_data/randIUPAC1000.fasta",
+        ..Default::default()
+    };
+    let iupac = extract_first_sequence(&iupac).unwrap().into_bytes();
+    // Mostly unambiguous, with some runs of ambiguous codes.
+    let d00596 = Config {
+        input_file: "tests/test_data/d00596.fasta",
+        ..Default::default()
+    };
+    let mut sprinkled = extract_first_sequence(&d00596).unwrap().into_bytes();
+    for (pos, code) in (0..sprinkled.len())
+        .step_by(37)
+        .zip(b"nrywnnskmnbdhvnnn".iter().cycle())
+    {
+        sprinkled[pos] = *code;
+        sprinkled[pos / 2] = *code;
+    }
+
+    for match_mode in [
+        MatchMode::Permissive,
+        MatchMode::Strict,
+        MatchMode::AmbiguousMismatch,
+    ] {
+        for (min_len, max_len, max_gap, mismatches) in [(3, 20, 0, 0), (4, 20, 5, 2)] {
+            for rna in [false, true] {
+                let mut params = SearchParams::new(min_len, max_len, max_gap, mismatches).unwrap();
+                params.match_mode = match_mode.clone();
+                params.rna = rna;
+                assert_consistent_pairing(&params, &iupac);
+                let irs = assert_consistent_pairing(&params, &sprinkled);
+                assert!(!irs.is_empty());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_ambiguous_mismatch_unambiguous_seq() {
+    // Without ambiguous codes, the strict matrices are the same as the permissive one.
+    let config = Config {
+        input_file: "tests/test_data/d00596.fasta",
+        ..Default::default()
+    };
+    let seq = extract_first_sequence(&config).unwrap();
+    let mut params = SearchParams::new(5, 30, 10, 1).unwrap();
+    let expected = find_irs(&params, seq.as_bytes()).unwrap();
+    for match_mode in [MatchMode::Strict, MatchMode::AmbiguousMismatch] {
+        params.match_mode = match_mode;
+        assert_eq!(find_irs(&params, seq.as_bytes()).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_strict_n_runs() {
+    let seq = format!("acgtt{}aacgt", "n".repeat(50));
+    let mut params = SearchParams::new(4, 100, 3, 1).unwrap();
+    assert!(find_irs(&params, seq.as_bytes()).unwrap().len() > 50);
+
+    params.match_mode = MatchMode::Strict;
+    let irs = assert_consistent_pairing(&params, seq.as_bytes());
+    assert!(irs.is_empty(), "{irs:?}");
+
+    // Each n is a mismatch, so the arms can cross one of them.
+    params.match_mode = MatchMode::AmbiguousMismatch;
+    let seq = b"ccacgtnacgtaagg";
+    let irs = assert_consistent_pairing(&params, seq);
+    assert!(irs.iter().any(|ir| ir.left_arm().contains(&6)), "{irs:?}");
+}
+
+#[test]
+fn test_strict_ambiguous_near_center() {
+    // The ambiguous codes are within the gap allowed around the center, but must not be
+    // in the arms
+    let seq = b"cnagatattgaacngtcgcnnnaacncntaaagggcca";
+    let mut params = SearchParams::new(3, 8, 10, 2).unwrap();
+    params.match_mode = MatchMode::Strict;
+    let irs = assert_consistent_pairing(&params, seq);
+    assert!(!irs.is_empty());
+    assert!(
+        irs.iter().all(|ir| !ir.left_arm_seq(seq).contains(&b'n')),
+        "{irs:?}"
+    );
+
+    let config = Config {
+        input_file: "tests/test_data/randIUPAC1000.fasta",
+        ..Default::default()
+    };
+    let seq = extract_first_sequence(&config).unwrap().into_bytes();
+    for (min_len, max_len, max_gap, mismatches) in [(3, 8, 10, 2), (2, 5, 4, 1), (4, 10, 20, 3)] {
+        let mut params = SearchParams::new(min_len, max_len, max_gap, mismatches).unwrap();
+        params.match_mode = MatchMode::Strict;
+        assert_consistent_pairing(&params, &seq);
+    }
+}
+
+#[test]
+fn test_n_policies() {
+    let seq = format!("acgtt{}aacgt", "n".repeat(50));
+    let mut params = SearchParams::new(4, 100, 3, 1).unwrap();
+    for n_policy in [NPolicy::NeverMatch, NPolicy::Break] {
+        params.n_policy = n_policy;
+        let irs = assert_consistent_pairing(&params, seq.as_bytes());
+        assert!(irs.is_empty(), "{irs:?}");
+    }
+
+    // The arms "acrt" and "aygt" pair through the r·y pair, with the n in the loop.
+    let seq = b"ccacrtnaygtaagg";
+    let degenerate = InvertedRepeat::new(2, 10, 1);
+    params.n_policy = NPolicy::NeverMatch;
+    let irs = assert_consistent_pairing(&params, seq);
+    assert!(irs.contains(&degenerate), "{irs:?}");
+    // The n is a mismatch, that the arms can cross...
+    assert!(irs.iter().any(|ir| ir.left_arm().contains(&6)), "{irs:?}");
+    // ...unless it ends them.
+    params.n_policy = NPolicy::Break;
+    let irs = assert_consistent_pairing(&params, seq);
+    assert_eq!(irs, [degenerate]);
+}
+
+#[test]
+fn test_n_break_near_center() {
+    // The n is within the gap allowed around the center, but must not be in the arms
+    let seq = b"kcyaaknkgkgytgnytrycygcrkrnacattcat";
+    let mut params = SearchParams::new(2, 9, 4, 1).unwrap();
+    params.n_policy = NPolicy::Break;
+    let irs = assert_consistent_pairing(&params, seq);
+    assert!(!irs.is_empty());
+
+    // Every other character an n, in random sequences
+    let config = Config {
+        input_file: "tests/test_data/rand1000.fasta",
+        ..Default::default()
+    };
+    let seq = extract_first_sequence(&config).unwrap().into_bytes();
+    let mut found = 0;
+    for step in [3, 5, 7, 11] {
+        let seq: Vec<_> = seq
+            .iter()
+            .enumerate()
+            .map(|(idx, &b)| if idx % step == 0 { b'n' } else { b })
+            .collect();
+        for (min_len, max_len, max_gap, mismatches) in [(2, 9, 4, 1), (3, 6, 9, 2), (2, 5, 1, 1)] {
+            let mut params = SearchParams::new(min_len, max_len, max_gap, mismatches).unwrap();
+            params.n_policy = NPolicy::Break;
+            found += assert_consistent_pairing(&params, &seq).len();
+        }
+    }
+    assert!(found > 0);
+}
+
+#[test]
+fn test_zero_edits_same_as_exact() {
+    for input_file in [
+        "tests/test_data/d00596.fasta",
+        "tests/test_data/randIUPAC1000.fasta",
+    ] {
+        let config = Config {
+            input_file,
+            ..Default::default()
+        };
+        let seq = extract_first_sequence(&config).unwrap().into_bytes();
+        for (min_len, max_len, max_gap) in [(3, 20, 0), (4, 20, 5), (6, 10, 11), (2, 5, 3)] {
+            let mut params = SearchParams::new(min_len, max_len, max_gap, 0).unwrap();
+            let expected: Vec<_> = find_irs(&params, &seq)
+                .unwrap()
+                .iter()
+                .map(InvertedRepeat::as_tuple)
+                .collect();
+            params.max_edits = Some(0);
+            let irs = find_irs(&params, &seq).unwrap();
+            assert!(irs.iter().all(|ir| ir.alignment.is_some()));
+            let received: Vec<_> = irs.iter().map(InvertedRepeat::as_tuple).collect();
+            assert_eq!(received, expected);
+        }
+    }
+}
+
+#[test]
+fn test_edits_consistent() {
+    let config = Config {
+        input_file: "tests/test_data/randIUPAC1000.fasta",
+        ..Default::default()
+    };
+    let seq = extract_first_sequence(&config).unwrap().into_bytes();
+    for (min_len, max_len, max_gap, max_edits) in [(4, 20, 5, 1), (6, 15, 3, 2), (8, 30, 0, 3)] {
+        let mut params = SearchParams::new(min_len, max_len, max_gap, 0).unwrap();
+        params.max_edits = Some(max_edits);
+        let matrix = params.matrix();
+        let irs = find_irs(&params, &seq).unwrap();
+        assert!(irs.iter().any(|ir| ir.arm_len() != ir.right_arm_len()));
+
+        for ir in &irs {
+            let alignment = ir.alignment.as_ref().unwrap();
+            assert!(ir.edits() <= max_edits, "{ir:?}");
+            assert!(ir.gap <= max_gap, "{ir:?}");
+            for arm_len in [ir.arm_len(), ir.right_arm_len()] {
+                assert!((min_len..=max_len).contains(&arm_len), "{ir:?}");
+            }
+            assert_eq!(alignment.ops.first(), Some(&b'='), "{ir:?}");
+            assert_eq!(alignment.ops.last(), Some(&b'='), "{ir:?}");
+
+            let mut mismatch_positions = Vec::new();
+            let columns = alignment.columns(ir.left, ir.right);
+            for ((l, r), &op) in columns.zip(&alignment.ops) {
+                match (l, r) {
+                    (Some(l), Some(r)) => {
+                        let pairs = matrix.pairs(seq[l], seq[r]);
+                        assert_eq!(pairs, op == b'=', "{ir:?}");
+                        if !pairs {
+                            mismatch_positions.push(l);
+                        }
+                    }
+                    (Some(_), None) => assert_eq!(op, b'I'),
+                    (None, Some(_)) => assert_eq!(op, b'D'),
+                    (None, None) => unreachable!(),
+                }
+            }
+            assert_eq!(ir.mismatch_positions, mismatch_positions, "{ir:?}");
+        }
+    }
+}
+
+#[test]
+fn test_edits_no_duplicate_spans() {
+    // An unpaired nucleotide next to the inner pair can be on either arm, with the gap
+    // moved by one: the span (3, 22, 1) is only reported once, with the longest left arm.
+    let seq = b"acgtaacgtcaggcctgacgttaacgttacgaacgtagtcgcaatggcatttagc";
+    let mut params = SearchParams::new(4, 10, 3, 0).unwrap();
+    params.max_edits = Some(1);
+    let irs = find_irs(&params, seq).unwrap();
+    let spans: Vec<_> = irs
+        .iter()
+        .filter(|ir| ir.as_tuple() == (3, 22, 1))
+        .collect();
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].alignment.as_ref().unwrap().left_len, 10);
+
+    let config = Config {
+        input_file: "tests/test_data/rand1000.fasta",
+        ..Default::default()
+    };
+    let seq = extract_first_sequence(&config).unwrap().into_bytes();
+    for (min_len, max_len, max_gap, max_edits) in [(4, 10, 3, 1), (5, 20, 6, 2), (6, 15, 0, 3)] {
+        let mut params = SearchParams::new(min_len, max_len, max_gap, 0).unwrap();
+        params.max_edits = Some(max_edits);
+        let irs = find_irs(&params, &seq).unwrap();
+        let mut spans: Vec<_> = irs.iter().map(InvertedRepeat::as_tuple).collect();
+        spans.sort_unstable();
+        spans.dedup();
+        assert_eq!(spans.len(), irs.len());
+        assert_eq!(irs, find_irs_chunked(&params, &seq, 100).unwrap());
+    }
+}
+
+#[test]
+fn test_edits_insertion() {
+    // The arms gcatgcaatc and gattagcatgc pair with an extra a in the right arm
+    let seq = b"ttttgcatgcaatcccccgattagcatgcgggg";
+    let mut params = SearchParams::new(6, 20, 6, 1).unwrap();
+    assert!(find_irs(&params, seq).unwrap().is_empty());
+
+    params.max_edits = Some(1);
+    let irs = find_irs(&params, seq).unwrap();
+    let ir = &irs[0];
+    assert_eq!(ir.as_tuple(), (4, 28, 4));
+    assert_eq!(ir.left_arm_seq(seq), b"gcatgcaatc");
+    assert_eq!(ir.right_arm_seq(seq), b"gattagcatgc");
+    assert_eq!(ir.alignment.as_ref().unwrap().cigar(), "6=1D4=");
+}
+
+#[test]
+fn test_mirror_same_as_complemented_matrix() {
+    // Pairing the codes that share a base (instead of a complementary base) turns the
+    // search of IRs into the one of mirror repeats.
+    let config = Config {
+        input_file: "tests/test_data/randIUPAC1000.fasta",
+        ..Default::default()
+    };
+    let seq = extract_first_sequence(&config).unwrap().replace('u', "t");
+    let codes = "acgtrykmswbdhvn";
+    let permissive = matrix::MatchMatrix::new();
+    let mut text = format!(
+        "  {}\n",
+        codes
+            .chars()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    for x in codes.bytes() {
+        let row: Vec<_> = codes
+            .bytes()
+            .map(|y| if permissive.match_u8(x, y) { "1" } else { "0" })
+            .collect();
+        text.push_str(&format!("{} {}\n", x as char, row.join(" ")));
+    }
+    let shared_base = matrix::MatchMatrix::parse(&text).unwrap();
+
+    for (min_len, max_len, max_gap, mismatches) in [(4, 20, 5, 0), (6, 15, 3, 2), (8, 30, 10, 3)] {
+        let mut params = SearchParams::new(min_len, max_len, max_gap, mismatches).unwrap();
+        params.kind = RepeatKind::Mirror;
+        let mirror = find_irs(&params, seq.as_bytes()).unwrap();
+        assert!(!mirror.is_empty());
+        for ir in &mirror {
+            let left = ir.left_arm_seq(seq.as_bytes());
+            let right = ir.right_arm_seq(seq.as_bytes());
+            let mismatches = left
+                .iter()
+                .zip(right.iter().rev())
+                .filter(|&(&l, &r)| !permissive.match_u8(l, r))
+                .count();
+            assert_eq!(mismatches, ir.mismatches(), "{ir:?}");
+        }
+
+        params.kind = RepeatKind::Inverted;
+        params.match_mode = MatchMode::Custom(Arc::new(shared_base.clone()));
+        assert_eq!(mirror, find_irs(&params, seq.as_bytes()).unwrap());
+    }
+}
+
+/// The direct repeats of `seq`, extending the arms one pair at a time from every start.
+fn naive_direct_repeats(params: &SearchParams, seq: &[u8]) -> Vec<InvertedRepeat> {
+    let n = seq.len();
+    let matrix = params.matrix();
+    let mut repeats = Vec::new();
+    for d in params.min_len..n {
+        let max_arm = d.min(params.max_len);
+        let min_arm = params.min_len.max(d.saturating_sub(params.max_gap));
+        for a in 0..n - d {
+            let matches = |k: usize| matrix.match_u8(seq[a + k], seq[a + d + k]);
+            let after_mismatch = a == 0 || !matrix.match_u8(seq[a - 1], seq[a - 1 + d]);
+            if !after_mismatch || !matches(0) {
+                continue;
+            }
+            let (mut arm_len, mut mismatch_positions) = (0, Vec::new());
+            let mut pending = Vec::new();
+            for k in 0..max_arm.min(n - d - a) {
+                if matches(k) {
+                    arm_len = k + 1;
+                    mismatch_positions.append(&mut pending);
+                } else if matrix.breaks_u8(seq[a + k], seq[a + d + k])
+                    || mismatch_positions.len() + pending.len() == params.mismatches
+                {
+                    break;
+                } else {
+                    pending.push(a + k);
+                }
+            }
+            if arm_len >= min_arm {
+                repeats.push(InvertedRepeat {
+                    mismatch_positions,
+                    ..InvertedRepeat::new(a, a + d + arm_len - 1, d - arm_len)
+                });
+            }
+        }
+    }
+    repeats
+}
+
+#[test]
+fn test_direct_same_as_naive() {
+    for input_file in [
+        "tests/test_data/rand1000.fasta",
+        "tests/test_data/randIUPAC1000.fasta",
+    ] {
+        let config = Config {
+            input_file,
+            ..Default::default()
+        };
+        let seq = extract_first_sequence(&config).unwrap().into_bytes();
+        for (min_len, max_len, max_gap, mismatches) in
+            [(4, 20, 5, 0), (5, 12, 0, 1), (6, 15, 3, 2), (8, 30, 20, 3)]
+        {
+            let mut params = SearchParams::new(min_len, max_len, max_gap, mismatches).unwrap();
+            params.kind = RepeatKind::Direct;
+            let key = |ir: &InvertedRepeat| (ir.left, ir.right, ir.gap);
+            let mut expected = naive_direct_repeats(&params, &seq);
+            expected.sort_by_key(key);
+            let mut received = find_irs(&params, &seq).unwrap();
+            received.sort_by_key(key);
+            assert!(!received.is_empty());
+            assert_eq!(received, expected);
+        }
+    }
+}
+
+#[test]
+fn test_direct_tandem() {
+    let seq = b"ttacgtacgtacgttt";
+    let mut params = SearchParams::new(4, 10, 2, 0).unwrap();
+    params.kind = RepeatKind::Direct;
+    let irs = find_irs(&params, seq).unwrap();
+    // The arms start after a mismatch: the tandem repeat of period 4 is reported once,
+    // from its first copy, and is too short for a period of 8 (arms of at least 6).
+    assert_eq!(irs.len(), 1);
+    assert_eq!(irs[0].as_tuple(), (1, 8, 0));
+    assert_eq!(irs[0].left_arm_seq(seq), b"tacg");
+    assert_eq!(irs[0].right_arm_seq(seq), b"tacg");
+
+    // A dispersed repeat, with a spacer gg and a mismatch
+    let seq = b"ttacgtcaggacctcatt";
+    params.mismatches = 1;
+    let irs = find_irs(&params, seq).unwrap();
+    let ir = irs.iter().find(|ir| ir.gap == 2).unwrap();
+    assert_eq!(ir.as_tuple(), (2, 15, 2));
+    assert_eq!(ir.left_arm_seq(seq), b"acgtca");
+    assert_eq!(ir.right_arm_seq(seq), b"acctca");
+    assert_eq!(ir.mismatch_positions, [4]);
+}
+
+#[test]
+fn test_direct_max_edits_unsupported() {
+    let seq = b"ttacgtacgtacgttt";
+    let mut params = SearchParams::new(4, 10, 2, 0).unwrap();
+    params.kind = RepeatKind::Direct;
+    params.max_edits = Some(1);
+    assert!(params.check_supported().is_err());
+    assert!(find_irs(&params, seq).is_err());
+    assert!(find_irs_with(&params, seq, |_| Ok(())).is_err());
+    assert!(find_irs_chunked(&params, seq, 8).is_err());
+    assert!(find_irs_iter(&params, seq).is_err());
+    let index = IrIndex::build(seq).unwrap();
+    assert!(index.search(&params).is_err());
+    assert!(index.search_with(&params, |_| Ok(())).is_err());
+
+    params.circular = true;
+    assert!(find_irs(&params, seq).is_err());
+}
+
+/// The IRs of `irs` grouped by (doubled) center, which is `left + right` unless the arms
+/// have different lengths.
+fn by_center(irs: &[InvertedRepeat]) -> BTreeMap<usize, Vec<&InvertedRepeat>> {
+    let mut centers: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for ir in irs {
+        let gap = ir.gap_range();
+        centers.entry(gap.start + gap.end - 1).or_default().push(ir);
+    }
+    centers
+}
+
+#[test]
+fn test_report_modes() {
+    let config = Config {
+        input_file: "tests/test_data/randIUPAC1000.fasta",
+        ..Default::default()
+    };
+    let seq = extract_first_sequence(&config).unwrap().into_bytes();
+    for kind in [RepeatKind::Inverted, RepeatKind::Direct, RepeatKind::Mirror] {
+        for (min_len, max_len, max_gap, mismatches, max_edits) in [
+            (4, 6, 5, 1, None),
+            (5, 12, 0, 2, None),
+            (6, 8, 9, 3, None),
+            (6, 10, 5, 0, Some(2)),
+        ] {
+            if kind == RepeatKind::Direct && max_edits.is_some() {
+                continue;
+            }
+            let mut params = SearchParams::new(min_len, max_len, max_gap, mismatches).unwrap();
+            params.kind = kind;
+            params.max_edits = max_edits;
+            let all = find_irs(&params, &seq).unwrap();
+            params.report = ReportMode::Maximal;
+            let maximal = find_irs(&params, &seq).unwrap();
+            params.report = ReportMode::BestPerCenter;
+            let best = find_irs(&params, &seq).unwrap();
+
+            let all = by_center(&all);
+            let maximal = by_center(&maximal);
+            let best = by_center(&best);
+            // Without a gap, there is at most an IR per center
+            if max_gap > 0 {
+                assert!(maximal.values().flatten().count() < all.values().flatten().count());
+            }
+            // Every center keeps at least an IR
+            assert_eq!(
+                maximal.keys().collect::<Vec<_>>(),
+                all.keys().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                best.keys().collect::<Vec<_>>(),
+                all.keys().collect::<Vec<_>>()
+            );
+
+            for (center, irs) in &all {
+                let kept = &maximal[center];
+                for ir in irs {
+                    let is_kept = kept.contains(ir);
+                    // Nested in a wider IR, or as wide with a longer gap
+                    let is_maximal = !irs.iter().any(|other| {
+                        let within = other.left <= ir.left && ir.right <= other.right;
+                        let wider = other.right - other.left > ir.right - ir.left;
+                        within && (wider || other.gap < ir.gap)
+                    });
+                    assert_eq!(is_kept, is_maximal, "{ir:?}");
+                }
+
+                let [best] = best[center][..] else {
+                    panic!("More than one IR at center {center}");
+                };
+                assert!(irs.contains(&best));
+                let matching =
+                    |ir: &InvertedRepeat| ir.arm_len() + ir.right_arm_len() - 2 * ir.edits();
+                assert!(irs.iter().all(|ir| matching(ir) <= matching(best)));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_report_nested() {
+    let seq = b"ggacgtacgtaaccttacgtacgtcc";
+    let mut params = SearchParams::new(4, 20, 8, 1).unwrap();
+    let all = find_irs(&params, seq).unwrap();
+    let at_center = |irs: &[InvertedRepeat]| {
+        irs.iter()
+            .filter(|ir| ir.left + ir.right == 21)
+            .map(InvertedRepeat::as_tuple)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(at_center(&all), [(0, 21, 8), (2, 19, 4)]);
+
+    // The second is nested in the first, which has a mismatch and a longer gap
+    params.report = ReportMode::Maximal;
+    let maximal = find_irs(&params, seq).unwrap();
+    assert_eq!(at_center(&maximal), [(0, 21, 8)]);
+    assert_eq!(maximal.len(), all.len() - 1);
+
+    params.report = ReportMode::BestPerCenter;
+    let best = find_irs(&params, seq).unwrap();
+    assert_eq!(at_center(&best), [(2, 19, 4)]);
+}
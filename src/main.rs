@@ -1,38 +1,144 @@
-extern crate elapsed_time;
+use iirs::{Cli, Command, Config, IndexArgs, InvertedRepeat, IrIndex, IrWriter, STDOUT_PATH};
+use iirs::{find_irs_chunked_with, find_irs_in_regions_with, find_irs_with};
 
-use iirs::Cli;
-use iirs::{find_irs, stringify_irs};
-
-use anyhow::Result;
+use anyhow::{Result, bail};
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
+
+/// Print a status message, to stderr if stdout is taken by the IRs.
+macro_rules! status {
+    ($to_stderr:expr, $($arg:tt)*) => {
+        if $to_stderr {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
 
-#[elapsed_time::elapsed]
 fn main() -> Result<()> {
+    let start = Instant::now();
     let args = Cli::parse_args();
+    let to_stdout = args.command.is_none() && args.output_path == Path::new(STDOUT_PATH);
+
+    let result = match &args.command {
+        Some(Command::Index(index_args)) => write_indexes(index_args),
+        None => write_irs(&args, to_stdout),
+    };
+
+    status!(to_stdout, "main took {:?}", start.elapsed());
+    result
+}
+
+fn write_irs(args: &Cli, to_stdout: bool) -> Result<()> {
     let check_bounds = true;
     let config_record_pairs = args.try_from_args(check_bounds)?;
+    let mut total_irs = 0;
 
-    for (config, record) in config_record_pairs {
-        let irs = find_irs(&config.params, &record.seq)?;
-        let (header, irs_str) = stringify_irs(&config, &irs, &record.seq);
+    for (nth, (config, record)) in config_record_pairs.iter().enumerate() {
+        // The IRs are written as they are found, instead of being collected first
+        let out: Box<dyn Write> = if to_stdout {
+            Box::new(BufWriter::new(io::stdout().lock()))
+        } else {
+            // Create folder(s) if we are scanning multiple sequences
+            create_parent_dirs(&config.output_path)?;
+            Box::new(BufWriter::new(File::create(&config.output_path)?))
+        };
+        // On stdout the sequences follow each other
+        let writer = if nth > 0 && to_stdout {
+            IrWriter::new_following(out, config, &record.seq, total_irs)?
+        } else {
+            IrWriter::new(out, config, &record.seq)?
+        };
+        let mut writer = writer
+            .with_features(&record.features)
+            .with_soft_mask(record.soft_mask.as_ref());
+        search(config, &record.seq, |ir| Ok(writer.write(&ir)?))?;
+        let n_irs = writer.n_irs();
+        writer.finish()?;
+        total_irs += n_irs;
 
-        // Create folder(s) if we are scanning multiple sequences
-        if let Some(parent) = config.output_path.parent()
-            && !parent.as_os_str().is_empty()
-        {
-            fs::create_dir_all(parent)?;
+        if !args.quiet {
+            status!(to_stdout, "\n{config}");
+            status!(to_stdout, "Search complete for {}!", &config.seq_name);
+            status!(to_stdout, "Found n={n_irs} inverted repeats\n");
         }
+    }
 
-        let mut file = File::create(&config.output_path)?;
-        writeln!(&mut file, "{}\n{}", &header, &irs_str)?;
+    Ok(())
+}
+
+/// Find the IRs of a sequence, using the prebuilt index or the windows of the config, if any.
+fn search<F>(config: &Config, seq: &[u8], f: F) -> Result<()>
+where
+    F: FnMut(InvertedRepeat) -> Result<()>,
+{
+    if let Some(index_path) = &config.index_path {
+        let index = IrIndex::load(index_path)?;
+        if index.seq() != seq {
+            bail!(
+                "Index '{}' was not built from sequence '{}'.",
+                index_path.display(),
+                config.seq_name
+            )
+        }
+        return match &config.regions {
+            Some(regions) => index.search_regions_with(&config.params, regions, f),
+            None => index.search_with(&config.params, f),
+        };
+    }
+
+    match (&config.regions, config.window_size) {
+        (Some(regions), Some(window_size)) => {
+            if window_size == 0 {
+                bail!("window_size must be greater than zero.")
+            }
+            let windows = split_regions(regions, window_size);
+            find_irs_in_regions_with(&config.params, seq, &windows, f)
+        }
+        (Some(regions), None) => find_irs_in_regions_with(&config.params, seq, regions, f),
+        (None, Some(window_size)) => find_irs_chunked_with(&config.params, seq, window_size, f),
+        (None, None) => find_irs_with(&config.params, seq, f),
+    }
+}
+
+/// Split the regions into windows of at most `window_size`.
+fn split_regions(regions: &[Range<usize>], window_size: usize) -> Vec<Range<usize>> {
+    regions
+        .iter()
+        .flat_map(|region| {
+            let end = region.end;
+            region
+                .clone()
+                .step_by(window_size)
+                .map(move |start| start..start.saturating_add(window_size).min(end))
+        })
+        .collect()
+}
+
+fn write_indexes(args: &IndexArgs) -> Result<()> {
+    for (index_path, record) in args.try_from_args()? {
+        let index = IrIndex::build(&record.seq)?;
+        create_parent_dirs(&index_path)?;
+        index.save(&index_path)?;
 
         if !args.quiet {
-            println!("\n{config}");
-            println!("Search complete for {}!", &config.seq_name);
-            println!("Found n={} inverted repeats\n", irs.len());
+            println!("Index written to {}", index_path.display());
         }
     }
 
     Ok(())
 }
+
+fn create_parent_dirs(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use crate::ir::InvertedRepeat;
+use std::ops::Range;
+
+/// The soft-masked positions of a sequence: those written in lowercase in the input file,
+/// as done by RepeatMasker and most genome assemblies for repeats.
+///
+/// Positions refer to the sanitized sequence (without newlines). Positions past its end
+/// wrap around to the start, as for the IRs of circular sequences.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoftMask {
+    bits: Vec<u64>,
+    len: usize,
+}
+
+impl SoftMask {
+    /// The soft mask of a raw sequence, ignoring newlines.
+    ///
+    /// Returns `None` unless the sequence mixes uppercase and lowercase letters: a sequence
+    /// written entirely in one case carries no masking information.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use iirs::SoftMask;
+    ///
+    /// let mask = SoftMask::from_seq(b"ACgt\nnA").unwrap();
+    /// assert_eq!(mask.len(), 6);
+    /// assert!(!mask.is_masked(1));
+    /// assert!(mask.is_masked(2));
+    /// assert_eq!(mask.count(0..6), 3);
+    ///
+    /// assert!(SoftMask::from_seq(b"acgt").is_none());
+    /// ```
+    pub fn from_seq(seq: &[u8]) -> Option<Self> {
+        let mut bits = vec![0; seq.len().div_ceil(64)];
+        let mut len = 0;
+        let (mut has_upper, mut has_lower) = (false, false);
+
+        for &byte in seq {
+            if byte == b'\n' || byte == b'\r' {
+                continue;
+            }
+            if byte.is_ascii_lowercase() {
+                bits[len / 64] |= 1 << (len % 64);
+                has_lower = true;
+            } else if byte.is_ascii_uppercase() {
+                has_upper = true;
+            }
+            len += 1;
+        }
+
+        bits.truncate(len.div_ceil(64));
+        (has_upper && has_lower).then_some(Self { bits, len })
+    }
+
+    /// Length of the masked sequence.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether position `pos` is soft-masked.
+    pub fn is_masked(&self, pos: usize) -> bool {
+        let pos = pos % self.len;
+        self.bits[pos / 64] & (1 << (pos % 64)) != 0
+    }
+
+    /// Number of soft-masked positions in `range`.
+    pub fn count(&self, range: Range<usize>) -> usize {
+        range.filter(|&pos| self.is_masked(pos)).count()
+    }
+
+    /// Fraction of the positions of both arms of `ir` that are soft-masked. The gap is not
+    /// taken into account.
+    pub fn masked_fraction(&self, ir: &InvertedRepeat) -> f64 {
+        let masked = self.count(ir.left_arm()) + self.count(ir.right_arm());
+        // The arms can have different lengths with indels
+        masked as f64 / (ir.arm_len() + ir.right_arm_len()) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::ArmAlignment;
+
+    #[test]
+    fn test_from_seq() {
+        let seq = [b"ACGT".repeat(20), b"acgt\r\n".repeat(20), b"N".to_vec()].concat();
+        let mask = SoftMask::from_seq(&seq).unwrap();
+        assert_eq!(mask.len(), 161);
+        assert_eq!(mask.count(0..161), 80);
+        assert_eq!(mask.count(78..82), 2);
+        assert!(!mask.is_masked(160));
+        // Positions wrap around
+        assert!(!mask.is_masked(161));
+        assert!(mask.is_masked(161 + 80));
+
+        assert!(SoftMask::from_seq(b"").is_none());
+        assert!(SoftMask::from_seq(b"ACGTN").is_none());
+        assert!(SoftMask::from_seq(b"ac\ngt").is_none());
+    }
+
+    #[test]
+    fn test_masked_fraction() {
+        let mask = SoftMask::from_seq(b"ACgtaaAcgt").unwrap();
+        // Arms "ACg" and "cgt", with a gap "taaA"
+        let ir = InvertedRepeat::new(0, 9, 4);
+        assert_eq!(mask.masked_fraction(&ir), 4.0 / 6.0);
+        // Wrapping around the origin: arms "gtaa" and "gtAC"
+        let ir = InvertedRepeat::new(2, 11, 2);
+        assert_eq!(mask.masked_fraction(&ir), 6.0 / 8.0);
+    }
+
+    #[test]
+    fn test_masked_fraction_indels() {
+        let mask = SoftMask::from_seq(b"ACgtaacgtt").unwrap();
+        // Arms "AC" and "cgtt" (with two unpaired nucleotides), with a gap "gtaa"
+        let ir = InvertedRepeat {
+            alignment: Some(ArmAlignment {
+                left_len: 2,
+                ops: b"=DD=".to_vec(),
+            }),
+            ..InvertedRepeat::new(0, 9, 4)
+        };
+        assert_eq!(ir.right_arm_seq(b"ACgtaacgtt"), b"cgtt");
+        assert_eq!(mask.masked_fraction(&ir), 4.0 / 6.0);
+    }
+}
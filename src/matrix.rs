@@ -1,7 +1,115 @@
-use crate::constants::{ALL_SYMBOLS_COUNT, build_iupac_rules};
-use std::collections::HashMap;
+use anyhow::{Context, Result, bail};
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::constants::{
+    ALL_SYMBOLS_COUNT, IUPAC_SYMBOLS, build_complement_array, build_iupac_rules,
+};
+use crate::utils::check_file_exist;
+
+/// How IUPAC characters are paired when comparing the arms of an IR.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Two codes pair if they share a complementary base: `n` pairs with everything
+    /// (same as `IUPACpal`).
+    #[default]
+    Permissive,
+    /// Only the unambiguous Watson-Crick pairs match, and an ambiguous code ends the arms.
+    Strict,
+    /// Only the unambiguous Watson-Crick pairs match: an ambiguous code is a mismatch.
+    AmbiguousMismatch,
+    /// User-defined pairs (see [`MatchMatrix::parse`]).
+    Custom(Arc<MatchMatrix>),
+}
+
+impl MatchMode {
+    /// The matrix used to compare the characters of the arms.
+    pub fn matrix(&self) -> MatchMatrix {
+        match self {
+            Self::Permissive => MatchMatrix::new(),
+            Self::Strict => {
+                let mut matrix = MatchMatrix::strict();
+                matrix.breaks = matrix.ambiguous;
+                matrix
+            }
+            Self::AmbiguousMismatch => MatchMatrix::strict(),
+            Self::Custom(matrix) => matrix.as_ref().clone(),
+        }
+    }
+}
+
+impl FromStr for MatchMode {
+    type Err = anyhow::Error;
+
+    /// Parse `permissive`, `strict`, `ambiguous-mismatch`, or load the custom matrix
+    /// from the file of the given path.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "permissive" => Self::Permissive,
+            "strict" => Self::Strict,
+            "ambiguous-mismatch" => Self::AmbiguousMismatch,
+            path => Self::Custom(Arc::new(MatchMatrix::load(path)?)),
+        })
+    }
+}
+
+impl std::fmt::Display for MatchMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmted = match self {
+            Self::Permissive => "permissive",
+            Self::Strict => "strict",
+            Self::AmbiguousMismatch => "ambiguous-mismatch",
+            Self::Custom(_) => "custom",
+        };
+        write!(f, "{fmted}")
+    }
+}
+
+/// How the `n` codes pair, whatever the [`MatchMode`]. Long runs of `n` fill the gaps of
+/// assembly scaffolds, and pairing them floods the output with meaningless IRs.
+///
+/// The other ambiguous codes are not affected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NPolicy {
+    /// `n` pairs as in the match mode (with everything, if permissive).
+    #[default]
+    MatchAll,
+    /// `n` pairs with nothing, not even `n`: it is a mismatch.
+    NeverMatch,
+    /// `n` pairs with nothing and ends the arms, like the end of the sequence.
+    Break,
+}
+
+impl FromStr for NPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "match-all" => Self::MatchAll,
+            "never-match" => Self::NeverMatch,
+            "break" => Self::Break,
+            _ => bail!("'{s}' is not an n policy: expected match-all, never-match or break."),
+        })
+    }
+}
+
+impl std::fmt::Display for NPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmted = match self {
+            Self::MatchAll => "match-all",
+            Self::NeverMatch => "never-match",
+            Self::Break => "break",
+        };
+        write!(f, "{fmted}")
+    }
+}
 
 /// Struct to check if two IUPAC characters match.
+///
+/// The second character is compared already complemented: `match_u8(b'a', b'a')` checks
+/// if an `a` pairs with a `t` on the other arm.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MatchMatrix {
     /// Linearized 1D bool array.
     match_matrix: [bool; ALL_SYMBOLS_COUNT * ALL_SYMBOLS_COUNT],
@@ -13,18 +121,46 @@ pub struct MatchMatrix {
     /// Ex. `iupac_char_to_index[36]` = 19
     /// since ord('$') = 36, which is the 19th key in `iupac_rules`.
     iupac_char_to_index: [usize; 128],
+    /// Which codes stand for more than one base.
+    ambiguous: [bool; ALL_SYMBOLS_COUNT],
+    /// Whether every character matches itself. If not, the runs of equal characters
+    /// found with the LCP array have to be checked.
+    all_self_match: bool,
+    /// The codes whose mismatches end the arms, instead of consuming the mismatch budget.
+    breaks: [bool; ALL_SYMBOLS_COUNT],
+    /// Linearized 1D bool array of the matches that are G·U wobble pairs.
+    wobble_matrix: [bool; ALL_SYMBOLS_COUNT * ALL_SYMBOLS_COUNT],
+}
+
+impl Default for MatchMatrix {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MatchMatrix {
+    /// The permissive matrix: two codes match if they share a base.
     pub fn new() -> Self {
+        Self::from_rule(|set1, set2| !set1.is_disjoint(set2))
+    }
+
+    /// Only the unambiguous bases match (with themselves).
+    pub fn strict() -> Self {
+        Self::from_rule(|set1, set2| set1.len() == 1 && set1 == set2)
+    }
+
+    /// Build the matrix from a rule on the sets of bases of two IUPAC chars.
+    fn from_rule(rule: impl Fn(&BTreeSet<char>, &BTreeSet<char>) -> bool) -> Self {
         let iupac_rules = build_iupac_rules();
 
         // HashMap from an IUPAC char to the set of its complements.
         let mut iupac_map = HashMap::new();
         let mut iupac_char_to_index = [0; 128];
+        let mut ambiguous = [false; ALL_SYMBOLS_COUNT];
         for (index, (iupac_char, mapped_chars)) in iupac_rules.iter().enumerate() {
             iupac_map.insert(*iupac_char, mapped_chars.to_owned());
             iupac_char_to_index[*iupac_char as usize] = index;
+            ambiguous[index] = mapped_chars.len() > 1;
         }
 
         let mut match_matrix = [false; ALL_SYMBOLS_COUNT * ALL_SYMBOLS_COUNT];
@@ -32,15 +168,207 @@ impl MatchMatrix {
             let i = iupac_char_to_index[*it1_char as usize];
             for (it2_char, it2_set) in &iupac_map {
                 let j = iupac_char_to_index[*it2_char as usize];
-                let matching = !it1_set.is_disjoint(it2_set);
+                let matching = rule(it1_set, it2_set);
                 match_matrix[i * ALL_SYMBOLS_COUNT + j] = matching;
             }
         }
 
+        Self::with_matrix(match_matrix, iupac_char_to_index, ambiguous)
+    }
+
+    fn with_matrix(
+        match_matrix: [bool; ALL_SYMBOLS_COUNT * ALL_SYMBOLS_COUNT],
+        iupac_char_to_index: [usize; 128],
+        ambiguous: [bool; ALL_SYMBOLS_COUNT],
+    ) -> Self {
+        let all_self_match =
+            (0..ALL_SYMBOLS_COUNT).all(|i| match_matrix[i * ALL_SYMBOLS_COUNT + i]);
         Self {
             match_matrix,
             iupac_char_to_index,
+            ambiguous,
+            all_self_match,
+            breaks: [false; ALL_SYMBOLS_COUNT],
+            wobble_matrix: [false; ALL_SYMBOLS_COUNT * ALL_SYMBOLS_COUNT],
+        }
+    }
+
+    /// Also match the G·U (and G·T) wobble pairs of RNA, if they do not already match.
+    ///
+    /// If `include_ambiguous`, so do the ambiguous codes that may stand for a wobble pair
+    /// (ex. `k`, g or t, with `u`), as in the permissive matrix.
+    #[must_use]
+    pub fn with_wobble(mut self, include_ambiguous: bool) -> Self {
+        let iupac_rules = build_iupac_rules();
+        // The second char is complemented: g pairs with u if it matches a, and u with g if
+        // it matches c.
+        let wobbles = |set1: &BTreeSet<char>, set2: &BTreeSet<char>| {
+            (set1.contains(&'g') && set2.contains(&'a'))
+                || (set1.contains(&'t') && set2.contains(&'c'))
+        };
+        for (it1_char, it1_set) in &iupac_rules {
+            let i = self.iupac_char_to_index[*it1_char as usize];
+            for (it2_char, it2_set) in &iupac_rules {
+                let j = self.iupac_char_to_index[*it2_char as usize];
+                let unambiguous = it1_set.len() == 1 && it2_set.len() == 1;
+                let ij = i * ALL_SYMBOLS_COUNT + j;
+                if !self.match_matrix[ij]
+                    && (include_ambiguous || unambiguous)
+                    && wobbles(it1_set, it2_set)
+                {
+                    self.match_matrix[ij] = true;
+                    self.wobble_matrix[ij] = true;
+                }
+            }
+        }
+        self
+    }
+
+    /// Apply the [`NPolicy`]: unless it is `MatchAll`, `n` no longer pairs with anything
+    /// (wobble pairs included).
+    #[must_use]
+    pub fn with_n_policy(mut self, policy: NPolicy) -> Self {
+        if policy == NPolicy::MatchAll {
+            return self;
+        }
+        let n = self.iupac_char_to_index[b'n' as usize];
+        for other in 0..ALL_SYMBOLS_COUNT {
+            for ij in [n * ALL_SYMBOLS_COUNT + other, other * ALL_SYMBOLS_COUNT + n] {
+                self.match_matrix[ij] = false;
+                self.wobble_matrix[ij] = false;
+            }
+        }
+        self.all_self_match = false;
+        self.breaks[n] = policy == NPolicy::Break;
+        self
+    }
+
+    /// Parse a matrix of the pairs of IUPAC codes that match.
+    ///
+    /// The first line lists the codes of the columns, and every following line starts with
+    /// the code of its row, followed by a `1` for every column it pairs with (`0` otherwise).
+    /// The pairs must be symmetric, and the codes not listed pair with nothing. Empty lines
+    /// and lines starting with `#` are skipped.
+    ///
+    /// The right arm is compared complemented, so `t` and `u` can not pair differently.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use iirs::MatchMatrix;
+    ///
+    /// let text = "
+    ///     ## Watson-Crick pairs, and n pairing with n.
+    ///       a c g t n
+    ///     a 0 0 0 1 0
+    ///     c 0 0 1 0 0
+    ///     g 0 1 0 0 0
+    ///     t 1 0 0 0 0
+    ///     n 0 0 0 0 1";
+    /// let matrix = MatchMatrix::parse(text).unwrap();
+    /// assert!(matrix.pairs(b'a', b't'));
+    /// assert!(matrix.pairs(b'n', b'n'));
+    /// assert!(!matrix.pairs(b'a', b'n'));
+    /// ```
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let parse_code = |code: &str| -> Result<u8> {
+            let code = code.to_ascii_lowercase();
+            match code.as_bytes() {
+                [byte] if IUPAC_SYMBOLS.contains(*byte as char) => Ok(*byte),
+                _ => bail!("'{code}' is not an IUPAC code."),
+            }
+        };
+
+        let Some(header) = lines.next() else {
+            bail!("The match matrix is empty.")
+        };
+        let columns = header
+            .split_whitespace()
+            .map(parse_code)
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut pairs = HashMap::new();
+        for line in lines {
+            let mut fields = line.split_whitespace();
+            let row = parse_code(fields.next().unwrap_or_default())?;
+            let values: Vec<_> = fields.collect();
+            if values.len() != columns.len() {
+                bail!(
+                    "Row '{}' has {} values for {} columns.",
+                    row as char,
+                    values.len(),
+                    columns.len()
+                )
+            }
+            for (&column, value) in columns.iter().zip(values) {
+                let pairing = match value {
+                    "0" => false,
+                    "1" => true,
+                    _ => bail!(
+                        "Invalid value '{value}' in row '{}', expected 0 or 1.",
+                        row as char
+                    ),
+                };
+                pairs.insert((row, column), pairing);
+            }
+        }
+
+        let mut matrix = Self::from_rule(|_, _| false);
+        // The sentinels keep matching themselves, as in the other matrices.
+        for sentinel in [b'$', b'#'] {
+            matrix.set(sentinel, sentinel, true);
+        }
+        let complement = build_complement_array();
+        let mut set_by = HashMap::new();
+        for (&(row, column), &pairing) in &pairs {
+            if pairs.get(&(column, row)).copied().unwrap_or(false) != pairing {
+                bail!(
+                    "The match matrix is not symmetric: '{}' and '{}'.",
+                    row as char,
+                    column as char
+                )
+            }
+            let compared = complement[column as usize];
+            if let Some(&(other, previous)) = set_by.get(&(row, compared))
+                && previous != pairing
+            {
+                bail!(
+                    "'{}' pairs differently with '{}' and '{}', which have the same complement.",
+                    row as char,
+                    column as char,
+                    other as char
+                )
+            }
+            set_by.insert((row, compared), (column, pairing));
+            matrix.set(row, compared, pairing);
         }
+        Ok(Self::with_matrix(
+            matrix.match_matrix,
+            matrix.iupac_char_to_index,
+            matrix.ambiguous,
+        ))
+    }
+
+    /// Load a matrix from a file, see [`MatchMatrix::parse`].
+    pub fn load(path: &str) -> Result<Self> {
+        check_file_exist(path)?;
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).with_context(|| format!("Invalid match matrix '{path}'."))
+    }
+
+    fn set(&mut self, b1: u8, b2: u8, matching: bool) {
+        let i = self.iupac_char_to_index[b1 as usize];
+        let j = self.iupac_char_to_index[b2 as usize];
+        self.match_matrix[i * ALL_SYMBOLS_COUNT + j] = matching;
+    }
+
+    /// Whether the code `b1` on one arm pairs with the code `b2` on the other.
+    pub fn pairs(&self, b1: u8, b2: u8) -> bool {
+        self.match_u8(b1, build_complement_array()[b2 as usize])
     }
 
     pub fn match_u8(&self, b1: u8, b2: u8) -> bool {
@@ -50,6 +378,32 @@ impl MatchMatrix {
         debug_assert!(j <= 20, "{b2}");
         self.match_matrix[i * ALL_SYMBOLS_COUNT + j]
     }
+
+    /// Whether `b1` and `b2` match only as a wobble pair (see [`MatchMatrix::with_wobble`]).
+    pub fn wobble_u8(&self, b1: u8, b2: u8) -> bool {
+        let i = self.iupac_char_to_index[b1 as usize];
+        let j = self.iupac_char_to_index[b2 as usize];
+        self.wobble_matrix[i * ALL_SYMBOLS_COUNT + j]
+    }
+
+    /// Length of the prefix of `run` that matches, when every character is compared with
+    /// itself (an exact match of the LCP array).
+    #[inline]
+    pub(crate) fn matching_prefix(&self, run: &[u8]) -> usize {
+        if self.all_self_match {
+            return run.len();
+        }
+        run.iter()
+            .position(|&b| !self.match_u8(b, b))
+            .unwrap_or(run.len())
+    }
+
+    /// Whether the mismatch between `b1` and `b2` ends the arms.
+    #[inline]
+    pub(crate) fn breaks_u8(&self, b1: u8, b2: u8) -> bool {
+        self.breaks[self.iupac_char_to_index[b1 as usize]]
+            || self.breaks[self.iupac_char_to_index[b2 as usize]]
+    }
 }
 
 #[cfg(test)]
@@ -147,4 +501,112 @@ mod tests {
         let complement = build_complement_array();
         assert!(!matrix.match_u8(b'k', complement['u' as usize]));
     }
+
+    #[test]
+    fn test_strict() {
+        let matrix = MatchMatrix::strict();
+        assert!(matrix.pairs(b'a', b't'));
+        assert!(matrix.pairs(b'u', b'a'));
+        assert!(matrix.pairs(b'g', b'c'));
+        assert!(!matrix.pairs(b'g', b'u'));
+        assert!(!matrix.pairs(b'n', b'n'));
+        assert!(!matrix.pairs(b'r', b'y'));
+        assert!(!matrix.all_self_match);
+        assert!(MatchMatrix::new().all_self_match);
+    }
+
+    #[test]
+    fn test_wobble() {
+        let permissive = MatchMatrix::new().with_wobble(true);
+        assert!(permissive.pairs(b'g', b'u'));
+        assert!(permissive.pairs(b'u', b'g'));
+        assert!(permissive.pairs(b'g', b't'));
+        assert!(permissive.pairs(b'k', b'u'));
+        assert!(!permissive.pairs(b'a', b'c'));
+        let complement = build_complement_array();
+        assert!(permissive.wobble_u8(b'g', complement[b'u' as usize]));
+        assert!(permissive.wobble_u8(b'k', complement[b'u' as usize]));
+        // Watson-Crick pairs are not wobble pairs, even if they could be.
+        assert!(!permissive.wobble_u8(b'g', complement[b'c' as usize]));
+        assert!(!permissive.wobble_u8(b'g', complement[b'y' as usize]));
+
+        let strict = MatchMatrix::strict().with_wobble(false);
+        assert!(strict.pairs(b'g', b'u'));
+        assert!(!strict.pairs(b'k', b'u'));
+        assert!(!strict.pairs(b'n', b'n'));
+        assert!(!MatchMatrix::new().wobble_u8(b'g', b'a'));
+    }
+
+    #[test]
+    fn test_breaks() {
+        let strict = MatchMode::Strict.matrix();
+        assert!(strict.breaks_u8(b'n', b'a'));
+        assert!(!strict.breaks_u8(b'a', b'c'));
+        assert!(!MatchMode::AmbiguousMismatch.matrix().breaks_u8(b'n', b'a'));
+    }
+
+    #[test]
+    fn test_n_policy() {
+        let permissive = MatchMatrix::new();
+        assert_eq!(
+            permissive.clone().with_n_policy(NPolicy::MatchAll),
+            permissive
+        );
+        for policy in [NPolicy::NeverMatch, NPolicy::Break] {
+            let matrix = MatchMode::Permissive.matrix().with_n_policy(policy);
+            assert!(!matrix.pairs(b'n', b'n'));
+            assert!(!matrix.pairs(b'a', b'n'));
+            assert!(!matrix.pairs(b'n', b'g'));
+            assert!(matrix.pairs(b'r', b'y'));
+            assert_eq!(matrix.matching_prefix(b"acnn"), 2);
+            assert_eq!(matrix.breaks_u8(b'n', b'a'), policy == NPolicy::Break);
+            assert!(!matrix.breaks_u8(b'r', b'a'));
+        }
+        let wobble = MatchMatrix::new()
+            .with_wobble(true)
+            .with_n_policy(NPolicy::NeverMatch);
+        assert!(!wobble.wobble_u8(b'n', b'a'));
+        assert_eq!(
+            "never-match".parse::<NPolicy>().unwrap(),
+            NPolicy::NeverMatch
+        );
+        assert!("never".parse::<NPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_parse_same_as_strict() {
+        let text = "  a c g t u\na 0 0 0 1 1\nc 0 0 1 0 0\ng 0 1 0 0 0\nt 1 0 0 0 0\nu 1 0 0 0 0\n";
+        let matrix = MatchMatrix::parse(text).unwrap();
+        for b1 in IUPAC_SYMBOLS.bytes() {
+            for b2 in IUPAC_SYMBOLS.bytes() {
+                assert_eq!(matrix.pairs(b1, b2), MatchMatrix::strict().pairs(b1, b2));
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let asymmetric = "  a t\na 0 1\nt 0 0";
+        let err = MatchMatrix::parse(asymmetric).unwrap_err().to_string();
+        assert!(err.contains("not symmetric"), "{err}");
+
+        let same_complement = "  a t u\na 0 1 0\nt 1 0 0\nu 0 0 0";
+        let err = MatchMatrix::parse(same_complement).unwrap_err().to_string();
+        assert!(err.contains("same complement"), "{err}");
+
+        assert!(MatchMatrix::parse("  a j\na 0 0\nj 0 0").is_err());
+        assert!(MatchMatrix::parse("  a t\na 0 1").is_err());
+        assert!(MatchMatrix::parse("  a t\na 0 2\nt 2 0").is_err());
+        assert!(MatchMatrix::parse("# empty").is_err());
+    }
+
+    #[test]
+    fn test_match_mode_from_str() {
+        assert_eq!("strict".parse::<MatchMode>().unwrap(), MatchMode::Strict);
+        assert_eq!(
+            "ambiguous-mismatch".parse::<MatchMode>().unwrap(),
+            MatchMode::AmbiguousMismatch
+        );
+        assert!("missing_matrix_file".parse::<MatchMode>().is_err());
+    }
 }
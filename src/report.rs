@@ -0,0 +1,161 @@
+use anyhow::{Result, bail};
+use std::str::FromStr;
+
+use crate::ir::InvertedRepeat;
+
+/// Which of the IRs found at the same center are reported.
+///
+/// A strong palindrome is reported once for every valid start of its arms (and truncated
+/// to `max_len`), so a single locus can produce many nested IRs sharing its center.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportMode {
+    /// Every IR within the thresholds.
+    #[default]
+    All,
+    /// Only the IRs not nested in a longer IR of the same center: the widest ones, with the
+    /// shortest gap.
+    Maximal,
+    /// Only the best IR of every center: the one with more matching nucleotides, then with
+    /// fewer edits, then with a shorter gap.
+    BestPerCenter,
+}
+
+impl FromStr for ReportMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "all" => Self::All,
+            "maximal" => Self::Maximal,
+            "best-per-center" => Self::BestPerCenter,
+            _ => bail!("'{s}' is not a report mode: expected all, maximal or best-per-center."),
+        })
+    }
+}
+
+impl std::fmt::Display for ReportMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmted = match self {
+            Self::All => "all",
+            Self::Maximal => "maximal",
+            Self::BestPerCenter => "best-per-center",
+        };
+        write!(f, "{fmted}")
+    }
+}
+
+impl ReportMode {
+    /// Keep the reported IRs among `irs`, all of them sharing the same center. The order
+    /// of the kept IRs is preserved.
+    pub(crate) fn select_at_center(self, mut irs: Vec<InvertedRepeat>) -> Vec<InvertedRepeat> {
+        match self {
+            Self::All => {}
+            Self::Maximal => {
+                let nested: Vec<_> = irs
+                    .iter()
+                    .map(|ir| irs.iter().any(|other| is_nested(ir, other)))
+                    .collect();
+                let mut nested = nested.into_iter();
+                irs.retain(|_| !nested.next().unwrap());
+            }
+            Self::BestPerCenter => {
+                // The first of the best, as max_by_key would keep the last
+                let best = irs
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, ir)| std::cmp::Reverse(best_key(ir)))
+                    .map(|(idx, _)| idx);
+                irs = best.map(|idx| irs.swap_remove(idx)).into_iter().collect();
+            }
+        }
+        irs
+    }
+
+    /// Same as [`ReportMode::select_at_center`], for `irs` sorted by center.
+    pub(crate) fn select(self, irs: Vec<InvertedRepeat>) -> Vec<InvertedRepeat> {
+        if self == Self::All {
+            return irs;
+        }
+        irs.chunk_by(|a, b| a.left + a.right == b.left + b.right)
+            .flat_map(|irs| self.select_at_center(irs.to_vec()))
+            .collect()
+    }
+}
+
+/// Whether `inner` spans part of `outer`, or all of it with a longer gap (shorter arms).
+fn is_nested(inner: &InvertedRepeat, outer: &InvertedRepeat) -> bool {
+    outer.left <= inner.left
+        && inner.right <= outer.right
+        && (outer.left < inner.left || inner.right < outer.right || outer.gap < inner.gap)
+}
+
+/// The larger, the better: the matching nucleotides of both arms, then the fewer edits,
+/// then the shorter gap.
+fn best_key(ir: &InvertedRepeat) -> (usize, std::cmp::Reverse<usize>, std::cmp::Reverse<usize>) {
+    let arms = ir.arm_len() + ir.right_arm_len();
+    (
+        arms.saturating_sub(2 * ir.edits()),
+        std::cmp::Reverse(ir.edits()),
+        std::cmp::Reverse(ir.gap),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_at_center() {
+        // Centered at 10: the first is nested in the others, the second has a longer gap
+        // than the last
+        let irs = vec![
+            InvertedRepeat::new(3, 17, 5),
+            InvertedRepeat::new(0, 20, 9),
+            InvertedRepeat {
+                mismatch_positions: vec![2],
+                ..InvertedRepeat::new(0, 20, 1)
+            },
+            InvertedRepeat::new(0, 20, 1),
+        ];
+
+        let all = ReportMode::All.select_at_center(irs.clone());
+        assert_eq!(all, irs);
+
+        // The last two only differ in their mismatches
+        let maximal = ReportMode::Maximal.select_at_center(irs.clone());
+        assert_eq!(maximal, irs[2..].to_vec());
+
+        let best = ReportMode::BestPerCenter.select_at_center(irs.clone());
+        assert_eq!(best, vec![irs[3].clone()]);
+
+        assert!(
+            ReportMode::BestPerCenter
+                .select_at_center(Vec::new())
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_select_groups_by_center() {
+        let irs = vec![
+            InvertedRepeat::new(2, 8, 1),
+            InvertedRepeat::new(1, 9, 1),
+            InvertedRepeat::new(3, 8, 2),
+            InvertedRepeat::new(2, 9, 0),
+        ];
+        let maximal = ReportMode::Maximal.select(irs.clone());
+        assert_eq!(maximal, vec![irs[1].clone(), irs[3].clone()]);
+    }
+
+    #[test]
+    fn test_report_mode_from_str() {
+        for mode in [
+            ReportMode::All,
+            ReportMode::Maximal,
+            ReportMode::BestPerCenter,
+        ] {
+            assert_eq!(mode.to_string().parse::<ReportMode>().unwrap(), mode);
+        }
+        assert!("longest".parse::<ReportMode>().is_err());
+    }
+}
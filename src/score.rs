@@ -0,0 +1,186 @@
+use anyhow::{Context, Result, bail};
+use std::str::FromStr;
+
+use crate::ir::InvertedRepeat;
+
+/// Weights of the score of an IR, to rank the IRs satisfying the thresholds.
+///
+/// The score adds up, for every nucleotide or pair of nucleotides of the IR, the weight of:
+/// - `matches`: a pair of the arms that matches (wobble pairs included, in RNA mode).
+/// - `mismatches`: a pair of the arms that is a mismatch.
+/// - `indels`: a nucleotide of an arm left unpaired (see [`crate::SearchParams::max_edits`]).
+/// - `ambiguous`: a nucleotide of the arms that is an ambiguous IUPAC code (not `acgtu`).
+/// - `gap`: a nucleotide of the gap (loop).
+///
+/// They are parsed from a comma separated list of `key=weight`, with the keys `match`,
+/// `mismatch`, `indel`, `ambiguous` and `gap`. The missing keys keep their default.
+///
+/// ```rust
+/// use iirs::{InvertedRepeat, ScoreWeights};
+///
+/// let weights: ScoreWeights = "mismatch=-2,gap=0".parse().unwrap();
+/// assert_eq!(weights.matches, 1.0);
+/// assert_eq!(weights.mismatches, -2.0);
+///
+/// // acgt aa acnt, with a mismatch (c, n)
+/// let seq = b"acgtaaacnt";
+/// let ir = InvertedRepeat {
+///     mismatch_positions: vec![1],
+///     ..InvertedRepeat::new(0, 9, 2)
+/// };
+/// assert_eq!(weights.score(&ir, seq), 3.0 - 2.0 - 0.5);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreWeights {
+    pub matches: f64,
+    pub mismatches: f64,
+    pub indels: f64,
+    pub ambiguous: f64,
+    pub gap: f64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self {
+            matches: 1.0,
+            mismatches: -1.0,
+            indels: -2.0,
+            ambiguous: -0.5,
+            gap: -0.1,
+        }
+    }
+}
+
+impl ScoreWeights {
+    /// Score of `ir`, found in `seq`. The positions are taken modulo the length of `seq`,
+    /// for the IRs wrapping around the origin of a circular sequence.
+    pub fn score(&self, ir: &InvertedRepeat, seq: &[u8]) -> f64 {
+        let unpaired = ir.edits() - ir.mismatches();
+        let pairs = (ir.arm_len() + ir.right_arm_len() - unpaired) / 2;
+        let matches = pairs - ir.mismatches();
+        let ambiguous = ir
+            .left_arm()
+            .chain(ir.right_arm())
+            .filter(|&pos| !b"acgtu".contains(&seq[pos % seq.len()]))
+            .count();
+
+        self.matches * matches as f64
+            + self.mismatches * ir.mismatches() as f64
+            + self.indels * unpaired as f64
+            + self.ambiguous * ambiguous as f64
+            + self.gap * ir.gap as f64
+    }
+}
+
+impl FromStr for ScoreWeights {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut weights = Self::default();
+        for item in s.split(',').filter(|item| !item.trim().is_empty()) {
+            let Some((key, value)) = item.split_once('=') else {
+                bail!("'{item}' is not a score weight: expected key=weight.")
+            };
+            let value: f64 = value
+                .trim()
+                .parse()
+                .with_context(|| format!("'{value}' is not the weight of '{key}'."))?;
+            if !value.is_finite() {
+                bail!("The weight of '{key}' must be a finite number.")
+            }
+            let weight = match key.trim() {
+                "match" => &mut weights.matches,
+                "mismatch" => &mut weights.mismatches,
+                "indel" => &mut weights.indels,
+                "ambiguous" => &mut weights.ambiguous,
+                "gap" => &mut weights.gap,
+                key => bail!(
+                    "'{key}' is not a score weight: expected match, mismatch, indel, ambiguous or gap."
+                ),
+            };
+            *weight = value;
+        }
+        Ok(weights)
+    }
+}
+
+impl std::fmt::Display for ScoreWeights {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "match={},mismatch={},indel={},ambiguous={},gap={}",
+            self.matches, self.mismatches, self.indels, self.ambiguous, self.gap
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::ArmAlignment;
+
+    #[test]
+    fn test_score_weights_from_str() {
+        let weights: ScoreWeights = "match=2, indel=-3.5".parse().unwrap();
+        assert_eq!(
+            weights,
+            ScoreWeights {
+                matches: 2.0,
+                indels: -3.5,
+                ..Default::default()
+            }
+        );
+        let default = ScoreWeights::default();
+        assert_eq!("".parse::<ScoreWeights>().unwrap(), default);
+        assert_eq!(
+            default.to_string().parse::<ScoreWeights>().unwrap(),
+            default
+        );
+
+        assert!("match".parse::<ScoreWeights>().is_err());
+        assert!("match=x".parse::<ScoreWeights>().is_err());
+        assert!("match=inf".parse::<ScoreWeights>().is_err());
+        assert!("loop=1".parse::<ScoreWeights>().is_err());
+    }
+
+    #[test]
+    fn test_score() {
+        let weights = ScoreWeights {
+            matches: 1.0,
+            mismatches: -10.0,
+            indels: -100.0,
+            ambiguous: -1000.0,
+            gap: -10000.0,
+        };
+        // acg ttaaa cgt
+        let seq = b"acgttaaacgt";
+        assert_eq!(
+            weights.score(&InvertedRepeat::new(0, 10, 5), seq),
+            3.0 - 50000.0
+        );
+
+        // acgt aa acnt: a mismatch and an ambiguous code
+        let seq = b"acgtaaacnt";
+        let ir = InvertedRepeat {
+            mismatch_positions: vec![1],
+            ..InvertedRepeat::new(0, 9, 2)
+        };
+        assert_eq!(weights.score(&ir, seq), 3.0 - 10.0 - 1000.0 - 20000.0);
+
+        // aacgt t acgt, with the first t of the left arm unpaired
+        let seq = b"aacgttacgtt";
+        let ir = InvertedRepeat {
+            alignment: Some(ArmAlignment {
+                left_len: 5,
+                ops: b"=I===".to_vec(),
+            }),
+            ..InvertedRepeat::new(0, 9, 1)
+        };
+        assert_eq!(ir.right_arm_len(), 4);
+        assert_eq!(weights.score(&ir, seq), 4.0 - 100.0 - 10000.0);
+
+        // Wrapping around the origin of a circular sequence
+        let ir = InvertedRepeat::new(9, 12, 0);
+        assert_eq!(weights.score(&ir, b"gtnnnnnnnac"), 2.0);
+    }
+}
@@ -1,6 +1,5 @@
 use crate::constants::IUPAC_SYMBOLS;
 use anyhow::{Result, anyhow};
-use seq_io::fasta::{OwnedRecord, Reader, Record};
 use std::fs;
 
 /// Just some clearer error handling.
@@ -16,17 +15,22 @@ pub fn check_file_exist(path: &str) -> Result<()> {
 }
 
 /// Remove newlines, cast to lowercase and check that all the character are in IUPAC.
+///
+/// The error tells the first invalid character, its position and the number of them
+/// (see [`ValidationReport`]).
 pub fn sanitize_sequence(seq: &[u8]) -> Result<Vec<u8>> {
-    let mut sanitized_seq = Vec::new();
+    sanitize_or_report(seq).map_err(|report| anyhow!("sequence {report}."))
+}
+
+/// Same as [`sanitize_sequence`], returning the report of the invalid characters instead.
+pub fn sanitize_or_report(seq: &[u8]) -> Result<Vec<u8>, ValidationReport> {
+    let mut sanitized_seq = Vec::with_capacity(seq.len());
 
     for &byte in seq {
         if byte != b'\n' && byte != b'\r' {
             let byte_lower = byte.to_ascii_lowercase();
             if !IUPAC_SYMBOLS.contains(byte_lower as char) {
-                return Err(anyhow!(
-                    "sequence contains '{}' which is not an IUPAC symbol.",
-                    byte as char
-                ));
+                return Err(validate_sequence(seq));
             }
             sanitized_seq.push(byte_lower);
         }
@@ -35,48 +39,107 @@ pub fn sanitize_sequence(seq: &[u8]) -> Result<Vec<u8>> {
     Ok(sanitized_seq)
 }
 
-/// Attempts to extract the record of every sequence with id in `seq_ids` from the input file.
-///
-/// If `seq_ids` is only `ALL_SEQUENCES` then all the sequences are extracted.
-/// For example:
-///
-/// `iirs -s ALL_SEQUENCES -m 5`
-///
-/// If at least one sequence is not found, returns an error with the list of missing
-/// sequences, together with a list of all the sequences present in the input file.
-pub fn safe_extract_records(input_file: &str, seq_ids: &[String]) -> Result<Vec<OwnedRecord>> {
-    check_file_exist(input_file)?;
-
-    let do_all_sequences = seq_ids == ["ALL_SEQUENCES"];
-
-    let mut reader = Reader::from_path(input_file)?;
-    let mut all_seq_ids_found = Vec::new();
-    let mut seq_ids_not_found = seq_ids.to_vec();
-    let mut records = Vec::new();
-
-    while let Some(record) = reader.next() {
-        let record = record.expect("Error reading record");
-        let mut owned_record = record.to_owned_record();
-        let record_id = owned_record.id()?.to_string();
-        if do_all_sequences || seq_ids.contains(&record_id) {
-            owned_record.seq = sanitize_sequence(record.seq())?;
-            records.push(owned_record);
-            seq_ids_not_found.retain(|id| id != &record_id);
-        }
+/// Same as [`sanitize_sequence`], replacing the characters that are not in IUPAC with
+/// `replacement`.
+pub fn sanitize_replacing(seq: &[u8], replacement: u8) -> Vec<u8> {
+    seq.iter()
+        .filter(|&&byte| byte != b'\n' && byte != b'\r')
+        .map(|byte| {
+            let byte_lower = byte.to_ascii_lowercase();
+            if IUPAC_SYMBOLS.contains(byte_lower as char) {
+                byte_lower
+            } else {
+                replacement
+            }
+        })
+        .collect()
+}
+
+/// What to do with the sequences that contain characters that are not in IUPAC.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InvalidPolicy {
+    /// Fail with the [`ValidationReport`] of the sequence.
+    #[default]
+    Error,
+    /// Skip the sequence.
+    SkipRecord,
+    /// Replace the invalid characters with this IUPAC symbol (usually `n`).
+    Replace(u8),
+}
+
+/// The characters of a sequence that are not IUPAC symbols.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Length of the sequence, without newlines.
+    pub len: usize,
+    /// Number of invalid characters.
+    pub invalid: usize,
+    /// The first invalid character, with its 1-based position (without newlines).
+    pub first_invalid: Option<(char, usize)>,
+    /// Number of invalid characters that are letters of amino acids.
+    amino_acids: usize,
+    /// Number of `x`: an unknown amino acid, but also the hard-masked bases of some DNA.
+    unknown: usize,
+}
 
-        all_seq_ids_found.push(record_id);
+impl ValidationReport {
+    pub const fn is_valid(&self) -> bool {
+        self.invalid == 0
     }
 
-    if !seq_ids_not_found.is_empty() && !do_all_sequences {
-        return Err(anyhow!(
-            "Sequence(s) '{}' not found.\nFound sequences in '{}' are:\n - {}",
-            seq_ids_not_found.join(", "),
-            input_file,
-            all_seq_ids_found.join("\n - ")
-        ));
+    /// Whether the sequence is rather a protein: the invalid characters are amino acids,
+    /// and there are too many of them to be typos (at least 5, and 5% of the sequence).
+    /// The `x` are ignored, as they can be found in both.
+    pub const fn looks_like_protein(&self) -> bool {
+        let invalid = self.invalid - self.unknown;
+        invalid >= 5 && self.amino_acids == invalid && invalid * 20 >= self.len
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some((byte, pos)) = self.first_invalid else {
+            return write!(f, "only contains IUPAC symbols");
+        };
+        write!(
+            f,
+            "contains '{byte}' which is not an IUPAC symbol, at position {pos} \
+            ({} invalid characters out of {})",
+            self.invalid, self.len
+        )?;
+        if self.looks_like_protein() {
+            write!(f, ". It looks like a protein sequence")?;
+        }
+        Ok(())
     }
+}
+
+/// Letters of the amino acids (and the ambiguous ones) that are not IUPAC nucleotide
+/// symbols.
+const AMINO_ACID_ONLY: &str = "efijlopqz";
 
-    Ok(records)
+/// Count the characters of `seq` that are not IUPAC symbols, ignoring newlines.
+pub fn validate_sequence(seq: &[u8]) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    for &byte in seq {
+        if byte == b'\n' || byte == b'\r' {
+            continue;
+        }
+        report.len += 1;
+        let byte_lower = byte.to_ascii_lowercase();
+        if !IUPAC_SYMBOLS.contains(byte_lower as char) {
+            report.invalid += 1;
+            if AMINO_ACID_ONLY.contains(byte_lower as char) {
+                report.amino_acids += 1;
+            } else if byte_lower == b'x' {
+                report.unknown += 1;
+            }
+            if report.first_invalid.is_none() {
+                report.first_invalid = Some((byte as char, report.len));
+            }
+        }
+    }
+    report
 }
 
 #[cfg(test)]
@@ -110,4 +173,49 @@ mod tests {
         let seq = b"de".to_vec();
         assert!(sanitize_sequence(&seq).is_err());
     }
+
+    #[test]
+    fn test_sanitize_sequence_error() {
+        let err = sanitize_sequence(b"acgt\nacjx\nac")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "sequence contains 'j' which is not an IUPAC symbol, at position 7 \
+            (2 invalid characters out of 10)."
+        );
+    }
+
+    #[test]
+    fn test_validate_sequence() {
+        let report = validate_sequence(b"ACGT\nNNAC");
+        assert!(report.is_valid());
+        assert_eq!(report.len, 8);
+
+        let protein = b"MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQ";
+        let report = validate_sequence(protein);
+        assert!(!report.is_valid());
+        assert_eq!(report.first_invalid, Some(('I', 6)));
+        assert!(report.looks_like_protein());
+        assert!(
+            report
+                .to_string()
+                .ends_with("It looks like a protein sequence")
+        );
+
+        // A few typos in a long sequence
+        let seq = [b"acgt".repeat(100), b"e".to_vec()].concat();
+        assert!(!validate_sequence(&seq).looks_like_protein());
+        // Not amino acids
+        assert!(!validate_sequence(b"ac12").looks_like_protein());
+        // Hard-masked DNA, and a protein with unknown residues
+        assert!(!validate_sequence(b"acgtXXXXXXXXXXacgt").looks_like_protein());
+        assert!(!validate_sequence(b"acgtXXXXXXXXXXacgtej").looks_like_protein());
+        assert!(validate_sequence(b"MKTAYIXXXXAKQRQISF").looks_like_protein());
+    }
+
+    #[test]
+    fn test_sanitize_replacing() {
+        assert_eq!(sanitize_replacing(b"ACj\nxT", b'n'), b"acnnt");
+    }
 }
@@ -1,4 +1,4 @@
-use iirs::{SearchParams, find_irs};
+use iirs::{NPolicy, SearchParams, find_irs};
 
 fn test_amount_irs(params: &SearchParams, string: &str) -> usize {
     let seq = string.to_ascii_lowercase().as_bytes().to_vec();
@@ -95,3 +95,21 @@ fn test_irs_full_n_no_gap() {
     let string = "N".repeat(500);
     assert_eq!(test_amount_irs(&params, &string), 481);
 }
+
+#[test]
+fn test_irs_full_n_never_match() {
+    let mut params = SearchParams::new(10, 100, 5, 1).unwrap();
+    params.n_policy = NPolicy::NeverMatch;
+    let string = "N".repeat(500);
+    assert_eq!(test_amount_irs(&params, &string), 0);
+}
+
+#[test]
+fn test_irs_full_n_break() {
+    let params = SearchParams {
+        n_policy: NPolicy::Break,
+        ..Default::default()
+    };
+    let string = "N".repeat(500);
+    assert_eq!(test_amount_irs(&params, &string), 0);
+}